@@ -10,14 +10,16 @@ pub struct FindArguments<T> {
 
 #[macro_export]
 macro_rules! args {
-    ($Args: ident <$generic: ident> { $($property: ident : $value: expr,)* $(,)?} ) => {
-        $Args::<$generic> {
+    ($Args: ident <$generic: ident> { $($property: ident : $value: expr,)* $(,)?} ) => {{
+        #[allow(clippy::needless_update)]
+        let args = $Args::<$generic> {
             $(
                 $property: args!($property : $value),
             )*
             ..Default::default()
-        }
-    };
+        };
+        args
+    }};
     (matcher : $value: expr) => {
         Box::new($value)
     };
@@ -0,0 +1,194 @@
+//! Time as seen by a FakeDb, for journal records and timestamp columns.
+use core::hash::Hash;
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use crate::{identifier::FallibleIdentifier, FakeDb};
+
+/// Tells a FakeDb the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// Reads the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a
+/// test can keep one to advance the clock of a FakeDb.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.time() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.time() += duration;
+    }
+
+    fn time(&self) -> MutexGuard<'_, SystemTime> {
+        crate::lock(&self.now)
+    }
+}
+
+/// Starts at the Unix epoch.
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.time()
+    }
+}
+
+type Setter<V> = Box<dyn Fn(&mut V, SystemTime) + Send + Sync>;
+
+/// Setters of the creation and update times of the values of a FakeDb.
+pub(crate) struct Timestamps<V> {
+    created: Setter<V>,
+    updated: Setter<V>,
+}
+
+impl<V> Timestamps<V> {
+    pub fn inserted(&self, value: &mut V, now: SystemTime) {
+        (self.created)(value, now);
+        (self.updated)(value, now);
+    }
+
+    pub fn updated(&self, value: &mut V, now: SystemTime) {
+        (self.updated)(value, now);
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Reads the time from `clock` instead of the system time.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Fills timestamps like `DEFAULT now()` and `ON UPDATE` triggers do:
+    /// `insert` and `insert_many` call both setters with the time of the
+    /// clock, while `update` and `update_many` only call `updated`.
+    ///
+    /// Timestamps are set before the hooks run, so before hooks see and may
+    /// override them.
+    pub fn with_timestamps<C, U>(mut self, created: C, updated: U) -> Self
+    where
+        C: Fn(&mut V, SystemTime) + Send + Sync + 'static,
+        U: Fn(&mut V, SystemTime) + Send + Sync + 'static,
+    {
+        self.timestamps = Some(Timestamps {
+            created: Box::new(created),
+            updated: Box::new(updated),
+        });
+        self
+    }
+
+    /// Current time of the clock of this FakeDb.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{args::UpdateArguments, identifier::Sequence};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Session {
+        user: &'static str,
+        created_at: Option<SystemTime>,
+        updated_at: Option<SystemTime>,
+    }
+
+    fn session(user: &'static str) -> Session {
+        Session {
+            user,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn db(clock: &ManualClock) -> FakeDb<u32, Session, Sequence> {
+        FakeDb::new(Sequence::new())
+            .with_clock(clock.clone())
+            .with_timestamps(
+                |session: &mut Session, now| session.created_at = Some(now),
+                |session: &mut Session, now| session.updated_at = Some(now),
+            )
+    }
+
+    #[test]
+    fn test_manual_clock_moves_when_told() {
+        let clock = ManualClock::default();
+        let shared = clock.clone();
+
+        shared.advance(Duration::from_secs(90));
+
+        assert_eq!(
+            clock.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(90)
+        );
+    }
+
+    #[test]
+    fn test_timestamps_are_filled_on_writes() {
+        let clock = ManualClock::default();
+        let db = db(&clock);
+        let start = clock.now();
+
+        db.insert_many(vec![session("ada"), session("grace")])
+            .unwrap();
+        clock.advance(Duration::from_secs(60));
+        db.update_many(UpdateArguments {
+            matcher: Box::new(|session: &&Session| session.user == "ada"),
+            updater: Box::new(|_: &mut Session| {}),
+        })
+        .unwrap();
+
+        let ada = db.find_by_id(&1).unwrap().unwrap();
+        let grace = db.find_by_id(&2).unwrap().unwrap();
+        assert_eq!(ada.created_at, Some(start));
+        assert_eq!(ada.updated_at, Some(start + Duration::from_secs(60)));
+        assert_eq!(grace.updated_at, Some(start));
+    }
+
+    #[test]
+    fn test_journal_records_the_time_of_the_clock() {
+        let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(3600));
+        let db = db(&clock).with_journal();
+
+        db.insert(session("ada")).unwrap();
+
+        assert_eq!(db.operations()[0].at, clock.now());
+    }
+}
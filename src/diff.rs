@@ -0,0 +1,216 @@
+//! Rows changed between two snapshots of a FakeDb.
+use core::hash::Hash;
+use std::fmt;
+
+use crate::{
+    identifier::FallibleIdentifier,
+    snapshot::Snapshot,
+    table::{line, natural_cmp},
+    FakeDb, Result, Rows,
+};
+
+/// A row that differs between two snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowDiff<K, V> {
+    Inserted { key: K, value: V },
+    Deleted { key: K, value: V },
+    Modified { key: K, before: V, after: V },
+}
+
+impl<K, V> RowDiff<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Self::Inserted { key, .. } | Self::Deleted { key, .. } | Self::Modified { key, .. } => {
+                key
+            }
+        }
+    }
+}
+
+/// Rows that differ between two snapshots, ordered by key like a
+/// `table::Table`.
+///
+/// Display prints a line per deleted or previous value prefixed with `-`,
+/// and a line per inserted or new value prefixed with `+`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diff<K, V> {
+    rows: Vec<RowDiff<K, V>>,
+}
+
+impl<K, V> Diff<K, V> {
+    pub fn rows(&self) -> &[RowDiff<K, V>] {
+        &self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Keys of all the rows that differ.
+    pub fn keys(&self) -> Vec<&K> {
+        self.rows.iter().map(RowDiff::key).collect()
+    }
+
+    pub fn inserted(&self) -> Vec<&K> {
+        self.keys_of(|row| matches!(row, RowDiff::Inserted { .. }))
+    }
+
+    pub fn deleted(&self) -> Vec<&K> {
+        self.keys_of(|row| matches!(row, RowDiff::Deleted { .. }))
+    }
+
+    pub fn modified(&self) -> Vec<&K> {
+        self.keys_of(|row| matches!(row, RowDiff::Modified { .. }))
+    }
+
+    fn keys_of(&self, kind: fn(&RowDiff<K, V>) -> bool) -> Vec<&K> {
+        self.rows
+            .iter()
+            .filter(|row| kind(row))
+            .map(RowDiff::key)
+            .collect()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Display for Diff<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = Vec::new();
+        for row in &self.rows {
+            let mut push = |sign: char, value: &V| {
+                let cells = [format!("{value:?}")];
+                lines.push(format!(
+                    "{sign} {}",
+                    line(&format!("{:?}", row.key()), &cells)
+                ));
+            };
+            match row {
+                RowDiff::Inserted { value, .. } => push('+', value),
+                RowDiff::Deleted { value, .. } => push('-', value),
+                RowDiff::Modified { before, after, .. } => {
+                    push('-', before);
+                    push('+', after);
+                }
+            }
+        }
+
+        f.write_str(&lines.join("\n"))
+    }
+}
+
+/// Rows inserted, deleted and modified from `before` to `after`.
+pub fn diff<K, V>(before: &Snapshot<K, V>, after: &Snapshot<K, V>) -> Diff<K, V>
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: Clone + PartialEq,
+{
+    diff_rows(before.rows(), after.rows())
+}
+
+pub(crate) fn diff_rows<K, V>(before: &Rows<K, V>, after: &Rows<K, V>) -> Diff<K, V>
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: Clone + PartialEq,
+{
+    let mut rows = Vec::new();
+    for (key, value) in before {
+        match after.get(key) {
+            Some(found) if found == value => {}
+            Some(found) => rows.push(RowDiff::Modified {
+                key: key.clone(),
+                before: value.clone(),
+                after: found.clone(),
+            }),
+            None => rows.push(RowDiff::Deleted {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+        }
+    }
+    for (key, value) in after {
+        if !before.contains_key(key) {
+            rows.push(RowDiff::Inserted {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    let mut keyed: Vec<(String, RowDiff<K, V>)> = rows
+        .into_iter()
+        .map(|row| (format!("{:?}", row.key()), row))
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+
+    Diff {
+        rows: keyed.into_iter().map(|(_, row)| row).collect(),
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Rows changed since `before` was taken, to assert exactly what an
+    /// operation changed.
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn diff_since(&self, before: &Snapshot<K, V>) -> Result<Diff<K, V>>
+    where
+        V: PartialEq,
+    {
+        Ok(diff(before, &self.snapshot()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::identifier::Sequence;
+
+    use super::*;
+
+    #[test]
+    fn test_diff_reports_changed_rows() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, "Bhutan"), (2, "Nepal"), (3, "Tibet")],
+        );
+        let before = db.snapshot().unwrap();
+
+        db.insert("Sikkim").unwrap();
+        db.update_many(crate::args::UpdateArguments {
+            matcher: Box::new(|name: &&&str| **name == "Nepal"),
+            updater: Box::new(|name: &mut &str| *name = "Gorkha"),
+        })
+        .unwrap();
+        db.delete_by_id(&3).unwrap();
+        let diff = db.diff_since(&before).unwrap();
+
+        assert_eq!(diff.inserted(), vec![&4]);
+        assert_eq!(diff.modified(), vec![&2]);
+        assert_eq!(diff.deleted(), vec![&3]);
+        assert_eq!(
+            diff.rows()[0],
+            RowDiff::Modified {
+                key: 2,
+                before: "Nepal",
+                after: "Gorkha"
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            "- 2 | \"Nepal\"\n+ 2 | \"Gorkha\"\n- 3 | \"Tibet\"\n+ 4 | \"Sikkim\""
+        );
+    }
+
+    #[test]
+    fn test_diff_of_unchanged_rows_is_empty() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Bhutan")]);
+        let before = db.snapshot().unwrap();
+
+        db.find_by_id(&1).unwrap();
+
+        assert!(db.diff_since(&before).unwrap().is_empty());
+    }
+}
@@ -1,55 +1,270 @@
-use http_problem::prelude::StatusCode;
+use std::fmt;
 
-http_problem::define_custom_type! {
-    type Conflict {
-        type: "https://http.cat/409",
-        title: "Conflict of keys",
-        status: StatusCode::INTERNAL_SERVER_ERROR,
-        detail(p): format!("Conflict in key {}", p.key),
-        extensions: {
-            key: String,
+use crate::{fault::Fault, operation::Operation};
+
+/// Errors returned by FakeDb operations.
+///
+/// It implements `std::error::Error`, and each enabled error adapter converts
+/// it into the error type of another crate, like the `http-problem` feature
+/// does for `http_problem::Problem`.
+///
+/// Every variant names the operation that failed and the table it ran on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FakeDbError {
+    /// A value was written with a key that is already in storage.
+    Conflict {
+        operation: Operation,
+        table: String,
+        key: String,
+    },
+    /// A value was updated with a key that is not in storage.
+    KeyNotFound {
+        operation: Operation,
+        table: String,
+        key: String,
+    },
+    /// Values written together have the same key.
+    Cardinality {
+        operation: Operation,
+        table: String,
+        key: String,
+    },
+    /// The identifier could not generate a key for a value.
+    InvalidId {
+        operation: Operation,
+        table: String,
+        reason: String,
+    },
+    /// The storage lock was poisoned.
+    Locking {
+        operation: Operation,
+        table: String,
+        message: String,
+    },
+    /// A failure injected through the FaultInjector of the FakeDb.
+    Fault {
+        operation: Operation,
+        table: String,
+        fault: Fault,
+    },
+    /// Rows could not be serialized or deserialized.
+    Format {
+        operation: Operation,
+        table: String,
+        message: String,
+    },
+    /// The files of a durable FakeDb could not be read or written.
+    Io {
+        operation: Operation,
+        table: String,
+        message: String,
+    },
+    /// A hook of the FakeDb rejected the row of `key`.
+    Rejected {
+        operation: Operation,
+        table: String,
+        key: String,
+        reason: String,
+    },
+    /// A fixture file could not be loaded, because of `error` at `line` when
+    /// it is known.
+    Fixture {
+        path: String,
+        line: Option<usize>,
+        error: Box<FakeDbError>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, FakeDbError>;
+
+/// Error of an identifier that could not generate a key for a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdError {
+    pub reason: String,
+}
+
+/// Error of a hook that rejects a row, see `hook::Hooks`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub reason: String,
+}
+
+impl Rejection {
+    pub fn new<R: Into<String>>(reason: R) -> Self {
+        Self {
+            reason: reason.into(),
         }
     }
 }
 
-http_problem::define_custom_type! {
-    type KeyNotFound {
-        type: "https://http.cat/404",
-        title: "Value not found in storage",
-        status: StatusCode::INTERNAL_SERVER_ERROR,
-        detail(p): format!("key not found  {}", p.key),
-        extensions: {
-            key: String,
+impl fmt::Display for FakeDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict {
+                operation,
+                table,
+                key,
+            } => write!(f, "{operation} on {table}: key {key} already exists"),
+            Self::KeyNotFound {
+                operation,
+                table,
+                key,
+            } => write!(f, "{operation} on {table}: key {key} not found"),
+            Self::Cardinality {
+                operation,
+                table,
+                key,
+            } => write!(f, "{operation} on {table}: key {key} is repeated"),
+            Self::InvalidId {
+                operation,
+                table,
+                reason,
+            } => write!(f, "{operation} on {table}: invalid id, {reason}"),
+            Self::Locking {
+                operation,
+                table,
+                message,
+            } => write!(
+                f,
+                "{operation} on {table}: unexpected error while locking {message}"
+            ),
+            Self::Fault {
+                operation,
+                table,
+                fault,
+            } => write!(f, "{operation} on {table}: {fault}"),
+            Self::Format {
+                operation,
+                table,
+                message,
+            } => write!(f, "{operation} on {table}: invalid data, {message}"),
+            Self::Io {
+                operation,
+                table,
+                message,
+            } => write!(f, "{operation} on {table}: io error, {message}"),
+            Self::Rejected {
+                operation,
+                table,
+                key,
+                reason,
+            } => write!(f, "{operation} on {table}: key {key} rejected, {reason}"),
+            Self::Fixture {
+                path,
+                line: Some(line),
+                error,
+            } => write!(f, "{path}:{line}: {error}"),
+            Self::Fixture {
+                path,
+                line: None,
+                error,
+            } => write!(f, "{path}: {error}"),
         }
     }
 }
 
-http_problem::define_custom_type! {
-    type Cardinality {
-        type: "https://http.cat/400",
-        title: "Values have conflicting ids",
-        status: StatusCode::BAD_REQUEST,
-        detail(p): format!("key not found  {}", p.key),
-        extensions: {
-            key: String,
+impl std::error::Error for FakeDbError {}
+
+/// The operation and table errors are reported for.
+pub(crate) struct Context<'a> {
+    pub operation: Operation,
+    pub table: &'a str,
+}
+
+impl Context<'_> {
+    pub fn conflict<K: fmt::Debug>(&self, key: &K) -> FakeDbError {
+        FakeDbError::Conflict {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            key: format!("{key:?}"),
+        }
+    }
+
+    pub fn key_not_found<K: fmt::Debug>(&self, key: &K) -> FakeDbError {
+        FakeDbError::KeyNotFound {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            key: format!("{key:?}"),
+        }
+    }
+
+    pub fn cardinality<K: fmt::Debug>(&self, key: &K) -> FakeDbError {
+        FakeDbError::Cardinality {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            key: format!("{key:?}"),
         }
     }
-}
 
-http_problem::define_custom_type! {
-    type Locking {
-        type: "https://http.cat/500",
-        title: "Error while locking value",
-        status: StatusCode::BAD_REQUEST,
-        detail(p): format!("Unexpected error while locking {}", p.message),
-        extensions: {
-            message: String
+    pub fn invalid_id(&self, err: IdError) -> FakeDbError {
+        FakeDbError::InvalidId {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            reason: err.reason,
+        }
+    }
+
+    pub fn rejected<K: fmt::Debug>(&self, key: &K, rejection: Rejection) -> FakeDbError {
+        FakeDbError::Rejected {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            key: format!("{key:?}"),
+            reason: rejection.reason,
+        }
+    }
+
+    pub fn fault(&self, fault: Fault) -> FakeDbError {
+        FakeDbError::Fault {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            fault,
+        }
+    }
+
+    pub fn format<E: fmt::Display>(&self, err: E) -> FakeDbError {
+        FakeDbError::Format {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            message: err.to_string(),
+        }
+    }
+
+    pub fn io<E: fmt::Display>(&self, err: E) -> FakeDbError {
+        FakeDbError::Io {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            message: err.to_string(),
+        }
+    }
+
+    pub fn locking<E: fmt::Display>(&self, err: E) -> FakeDbError {
+        FakeDbError::Locking {
+            operation: self.operation,
+            table: self.table.to_owned(),
+            message: err.to_string(),
         }
     }
 }
 
-pub(crate) fn locking<E: std::fmt::Display>(err: E) -> Locking {
-    Locking {
-        message: err.to_string(),
+#[cfg(feature = "http-problem")]
+pub mod problem;
+#[cfg(feature = "http-problem")]
+pub use problem::{
+    Cardinality, Conflict, Fixture, Format, InvalidId, Io, KeyNotFound, Locking, Rejected,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_is_a_std_error() {
+        let err: Box<dyn std::error::Error> = Box::new(FakeDbError::KeyNotFound {
+            operation: Operation::Update,
+            table: "countries".into(),
+            key: "55".into(),
+        });
+
+        assert_eq!(err.to_string(), "update on countries: key 55 not found");
     }
 }
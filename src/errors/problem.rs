@@ -0,0 +1,367 @@
+//! Adapter from FakeDbError to the problem types of `http_problem`.
+
+use http_problem::prelude::StatusCode;
+
+use super::FakeDbError;
+
+http_problem::define_custom_type! {
+    type Conflict {
+        type: "https://http.cat/409",
+        title: "Conflict of keys",
+        status: StatusCode::CONFLICT,
+        detail(p): format!("{} on {}: key {} already exists", p.operation, p.table, p.key),
+        extensions: {
+            operation: String,
+            table: String,
+            key: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type KeyNotFound {
+        type: "https://http.cat/404",
+        title: "Value not found in storage",
+        status: StatusCode::NOT_FOUND,
+        detail(p): format!("{} on {}: key {} not found", p.operation, p.table, p.key),
+        extensions: {
+            operation: String,
+            table: String,
+            key: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Cardinality {
+        type: "https://http.cat/400",
+        title: "Values have conflicting ids",
+        status: StatusCode::BAD_REQUEST,
+        detail(p): format!("{} on {}: key {} is repeated", p.operation, p.table, p.key),
+        extensions: {
+            operation: String,
+            table: String,
+            key: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type InvalidId {
+        type: "https://http.cat/400",
+        title: "Value has no valid id",
+        status: StatusCode::BAD_REQUEST,
+        detail(p): format!("{} on {}: invalid id, {}", p.operation, p.table, p.reason),
+        extensions: {
+            operation: String,
+            table: String,
+            reason: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Locking {
+        type: "https://http.cat/500",
+        title: "Error while locking value",
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        detail(p): format!(
+            "{} on {}: unexpected error while locking {}",
+            p.operation, p.table, p.message
+        ),
+        extensions: {
+            operation: String,
+            table: String,
+            message: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Fault {
+        type: "https://http.cat/503",
+        title: "Database failure",
+        status: StatusCode::SERVICE_UNAVAILABLE,
+        detail(p): format!("{} on {}: {}", p.operation, p.table, p.fault),
+        extensions: {
+            operation: String,
+            table: String,
+            fault: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Format {
+        type: "https://http.cat/400",
+        title: "Invalid serialized data",
+        status: StatusCode::BAD_REQUEST,
+        detail(p): format!("{} on {}: invalid data, {}", p.operation, p.table, p.message),
+        extensions: {
+            operation: String,
+            table: String,
+            message: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Io {
+        type: "https://http.cat/500",
+        title: "Error while persisting values",
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        detail(p): format!("{} on {}: io error, {}", p.operation, p.table, p.message),
+        extensions: {
+            operation: String,
+            table: String,
+            message: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Rejected {
+        type: "https://http.cat/422",
+        title: "Value rejected by a hook",
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+        detail(p): format!(
+            "{} on {}: key {} rejected, {}",
+            p.operation, p.table, p.key, p.reason
+        ),
+        extensions: {
+            operation: String,
+            table: String,
+            key: String,
+            reason: String,
+        }
+    }
+}
+
+http_problem::define_custom_type! {
+    type Fixture {
+        type: "https://http.cat/400",
+        title: "Invalid fixture file",
+        status: StatusCode::BAD_REQUEST,
+        detail(p): match p.line {
+            Some(line) => format!("{}:{}: {}", p.path, line, p.error),
+            None => format!("{}: {}", p.path, p.error),
+        },
+        extensions: {
+            path: String,
+            line: Option<usize>,
+            error: String,
+        }
+    }
+}
+
+impl From<FakeDbError> for http_problem::Problem {
+    fn from(err: FakeDbError) -> Self {
+        match err {
+            FakeDbError::Conflict {
+                operation,
+                table,
+                key,
+            } => Conflict {
+                operation: operation.to_string(),
+                table,
+                key,
+            }
+            .into(),
+            FakeDbError::KeyNotFound {
+                operation,
+                table,
+                key,
+            } => KeyNotFound {
+                operation: operation.to_string(),
+                table,
+                key,
+            }
+            .into(),
+            FakeDbError::Cardinality {
+                operation,
+                table,
+                key,
+            } => Cardinality {
+                operation: operation.to_string(),
+                table,
+                key,
+            }
+            .into(),
+            FakeDbError::InvalidId {
+                operation,
+                table,
+                reason,
+            } => InvalidId {
+                operation: operation.to_string(),
+                table,
+                reason,
+            }
+            .into(),
+            FakeDbError::Locking {
+                operation,
+                table,
+                message,
+            } => Locking {
+                operation: operation.to_string(),
+                table,
+                message,
+            }
+            .into(),
+            FakeDbError::Fault {
+                operation,
+                table,
+                fault,
+            } => Fault {
+                operation: operation.to_string(),
+                table,
+                fault: fault.to_string(),
+            }
+            .into(),
+            FakeDbError::Format {
+                operation,
+                table,
+                message,
+            } => Format {
+                operation: operation.to_string(),
+                table,
+                message,
+            }
+            .into(),
+            FakeDbError::Io {
+                operation,
+                table,
+                message,
+            } => Io {
+                operation: operation.to_string(),
+                table,
+                message,
+            }
+            .into(),
+            FakeDbError::Rejected {
+                operation,
+                table,
+                key,
+                reason,
+            } => Rejected {
+                operation: operation.to_string(),
+                table,
+                key,
+                reason,
+            }
+            .into(),
+            FakeDbError::Fixture { path, line, error } => Fixture {
+                path,
+                line,
+                error: error.to_string(),
+            }
+            .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fault, operation::Operation};
+
+    use super::*;
+
+    fn problem(err: FakeDbError) -> http_problem::Problem {
+        http_problem::Problem::from(err)
+    }
+
+    fn extension<'p>(problem: &'p http_problem::Problem, name: &str) -> Option<&'p str> {
+        problem
+            .extensions()
+            .into_iter()
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn test_error_converts_to_its_problem_type() {
+        let problem = problem(FakeDbError::Conflict {
+            operation: Operation::Insert,
+            table: "countries".into(),
+            key: "7".into(),
+        });
+
+        assert!(problem.is::<Conflict>());
+        assert_eq!(problem.type_(), "https://http.cat/409");
+        assert_eq!(
+            problem.details(),
+            "insert on countries: key 7 already exists"
+        );
+        assert_eq!(extension(&problem, "table"), Some("countries"));
+        assert_eq!(extension(&problem, "operation"), Some("insert"));
+        assert_eq!(extension(&problem, "key"), Some("7"));
+    }
+
+    #[test]
+    fn test_problem_status_matches_its_type() {
+        let table = || "countries".to_owned();
+        let operation = Operation::Update;
+        let errors = [
+            FakeDbError::Conflict {
+                operation,
+                table: table(),
+                key: "7".into(),
+            },
+            FakeDbError::KeyNotFound {
+                operation,
+                table: table(),
+                key: "7".into(),
+            },
+            FakeDbError::Cardinality {
+                operation,
+                table: table(),
+                key: "7".into(),
+            },
+            FakeDbError::InvalidId {
+                operation,
+                table: table(),
+                reason: "id is None".into(),
+            },
+            FakeDbError::Locking {
+                operation,
+                table: table(),
+                message: "poisoned".into(),
+            },
+            FakeDbError::Fault {
+                operation,
+                table: table(),
+                fault: fault::Fault::Timeout,
+            },
+            FakeDbError::Format {
+                operation,
+                table: table(),
+                message: "expected `,`".into(),
+            },
+            FakeDbError::Io {
+                operation,
+                table: table(),
+                message: "disk full".into(),
+            },
+            FakeDbError::Rejected {
+                operation,
+                table: table(),
+                key: "7".into(),
+                reason: "name is empty".into(),
+            },
+            FakeDbError::Fixture {
+                path: "countries.csv".into(),
+                line: Some(3),
+                error: Box::new(FakeDbError::Cardinality {
+                    operation,
+                    table: table(),
+                    key: "7".into(),
+                }),
+            },
+        ];
+
+        for err in errors {
+            let problem = problem(err);
+            let status = problem.status().as_u16().to_string();
+            assert!(problem.type_().to_string().ends_with(&status));
+        }
+    }
+}
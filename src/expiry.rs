@@ -0,0 +1,416 @@
+//! Rows with a time-to-live, hidden and reclaimed once the clock of the
+//! FakeDb passes their expiration time.
+use core::hash::Hash;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    errors::Result,
+    identifier::FallibleIdentifier,
+    observer::{Batch, Event},
+    wal::Change,
+    FakeDb, Operation, Rows,
+};
+
+type Extractor<V> = Arc<dyn Fn(&V) -> Option<SystemTime> + Send + Sync>;
+
+/// Expiration times of the rows of a FakeDb, either set when a row is
+/// inserted with a time-to-live or read from the row by an extractor when it
+/// is written.
+pub(crate) struct Expiry<K, V> {
+    deadlines: Mutex<Deadlines<K>>,
+    extractor: Option<Extractor<V>>,
+}
+
+#[derive(Clone)]
+struct Deadlines<K> {
+    /// Keys by expiration time, so only the expired ones are visited.
+    queue: BTreeMap<SystemTime, Vec<K>>,
+    keys: HashMap<K, Deadline>,
+}
+
+#[derive(Clone, Copy)]
+struct Deadline {
+    at: SystemTime,
+    /// Set by a time-to-live, which overrides the extractor.
+    ttl: bool,
+}
+
+impl<K, V> Default for Expiry<K, V> {
+    fn default() -> Self {
+        Self {
+            deadlines: Mutex::new(Deadlines {
+                queue: BTreeMap::new(),
+                keys: HashMap::new(),
+            }),
+            extractor: None,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> Expiry<K, V> {
+    /// Returns `true` if a row may expire, so writes must be tracked.
+    pub fn is_enabled(&self) -> bool {
+        self.extractor.is_some() || !self.deadlines().keys.is_empty()
+    }
+
+    /// Expires the row of `key` once `ttl` elapsed from `now`.
+    pub fn set_ttl(&self, key: K, now: SystemTime, ttl: Duration) {
+        let deadline = Deadline {
+            at: now + ttl,
+            ttl: true,
+        };
+        self.deadlines().set(key, Some(deadline));
+    }
+
+    /// Updates the deadlines of the rows written by `events`. A row inserted
+    /// again loses its time-to-live, while an updated row keeps it.
+    pub fn written(&self, events: &[Event<K, V>]) {
+        let mut deadlines = self.deadlines();
+        for event in events {
+            match event {
+                Event::Inserted { key, value } => {
+                    deadlines.set(key.clone(), self.extracted(value));
+                }
+                Event::Updated { key, new, .. } => {
+                    if !deadlines.keys.get(key).is_some_and(|deadline| deadline.ttl) {
+                        deadlines.set(key.clone(), self.extracted(new));
+                    }
+                }
+                Event::Deleted { key, .. } => deadlines.set(key.clone(), None),
+            }
+        }
+    }
+
+    /// Recomputes the deadlines for `rows` replacing the stored rows, keeping
+    /// the time-to-live of the rows still stored.
+    pub fn reset(&self, rows: &Rows<K, V>)
+    where
+        V: Clone,
+    {
+        let mut deadlines = self.deadlines();
+        let ttls: Vec<(K, Deadline)> = deadlines
+            .keys
+            .iter()
+            .filter(|(key, deadline)| deadline.ttl && rows.contains_key(*key))
+            .map(|(key, deadline)| (key.clone(), *deadline))
+            .collect();
+        deadlines.queue.clear();
+        deadlines.keys.clear();
+        if self.extractor.is_some() {
+            for (key, value) in rows {
+                deadlines.set(key.clone(), self.extracted(value));
+            }
+        }
+        for (key, deadline) in ttls {
+            deadlines.set(key, Some(deadline));
+        }
+    }
+
+    /// Copy of the deadlines and extractor, for a fork.
+    pub fn fork(&self) -> Self {
+        Self {
+            deadlines: Mutex::new(self.deadlines().clone()),
+            extractor: self.extractor.clone(),
+        }
+    }
+
+    /// Keys whose deadline passed at `now`.
+    fn expired(&self, now: SystemTime) -> Vec<K> {
+        self.deadlines()
+            .queue
+            .range(..=now)
+            .flat_map(|(_, keys)| keys.iter().cloned())
+            .collect()
+    }
+
+    /// Forgets the deadlines of `keys`, once their rows are removed.
+    fn forget(&self, keys: &[K]) {
+        let mut deadlines = self.deadlines();
+        for key in keys {
+            deadlines.set(key.clone(), None);
+        }
+    }
+
+    fn extracted(&self, value: &V) -> Option<Deadline> {
+        let extractor = self.extractor.as_ref()?;
+        extractor(value).map(|at| Deadline { at, ttl: false })
+    }
+
+    fn deadlines(&self) -> MutexGuard<'_, Deadlines<K>> {
+        crate::lock(&self.deadlines)
+    }
+}
+
+impl<K: Eq + Hash + Clone> Deadlines<K> {
+    /// Replaces the deadline of `key`.
+    fn set(&mut self, key: K, deadline: Option<Deadline>) {
+        if let Some(old) = self.keys.remove(&key) {
+            if let Some(keys) = self.queue.get_mut(&old.at) {
+                keys.retain(|queued| *queued != key);
+                if keys.is_empty() {
+                    self.queue.remove(&old.at);
+                }
+            }
+        }
+        if let Some(deadline) = deadline {
+            self.queue.entry(deadline.at).or_default().push(key.clone());
+            self.keys.insert(key, deadline);
+        }
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Expires every row once the clock reaches the time `expires_at`
+    /// returns for its value, like a DynamoDB TTL attribute. Rows for which
+    /// it returns `None` never expire.
+    ///
+    /// The time is read when a row is written, and for the rows already
+    /// stored.
+    pub fn with_expiry<E>(mut self, expires_at: E) -> Self
+    where
+        E: Fn(&V) -> Option<SystemTime> + Send + Sync + 'static,
+    {
+        self.expiry.extractor = Some(Arc::new(expires_at));
+        let rows = self
+            .storage
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner());
+        self.expiry.reset(rows);
+        self
+    }
+
+    /// Inserts `value` so it expires once `ttl` elapsed on the clock of this
+    /// FakeDb, overriding the time of `with_expiry`.
+    ///
+    /// The expiration time is not written to the write-ahead log, so rows
+    /// reloaded by `FakeDb::open` only expire through `with_expiry`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::insert`
+    pub fn insert_with_ttl(&self, value: V, ttl: Duration) -> Result<K> {
+        self.latency.block(Operation::Insert);
+        self.do_insert_with_ttl(value, ttl)
+    }
+
+    pub(crate) fn do_insert_with_ttl(&self, value: V, ttl: Duration) -> Result<K> {
+        let mut batch = self.batch();
+        let result = self
+            .insert_value(value, Some(ttl), &mut batch)
+            .map(|id| (vec![id.clone()], id));
+        let result = self.journaled(
+            Operation::Insert,
+            || format!("1 value, ttl {ttl:?}"),
+            result,
+        );
+        self.notified(batch, result)
+    }
+
+    /// Updates the expiration times of the rows written by the committed
+    /// `batch`.
+    pub(crate) fn record_deadlines(&self, batch: &Batch<K, V>) {
+        self.expiry.written(batch.events());
+    }
+
+    /// Removes the rows that expired from `storage`, before `operation` runs
+    /// on it. Expired rows are removed without events or hooks.
+    pub(crate) fn reclaim_expired(
+        &self,
+        operation: Operation,
+        storage: &mut Rows<K, V>,
+    ) -> Result<()> {
+        let expired = self.expiry.expired(self.clock.now());
+        if expired.is_empty() {
+            return Ok(());
+        }
+        let stored: Vec<&K> = expired
+            .iter()
+            .filter(|key| storage.contains_key(*key))
+            .collect();
+        self.log(operation, storage, || {
+            stored.iter().map(|&key| Change::Remove { key }).collect()
+        })?;
+        for key in &stored {
+            storage.remove(*key);
+        }
+        if self.history.is_enabled() {
+            let changes = stored.iter().map(|&key| (key, None));
+            self.history.record(changes, self.clock.now());
+        }
+        // Forgotten only once logged, so rows whose removal could not be
+        // logged expire on the next operation.
+        self.expiry.forget(&expired);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use crate::{
+        args::UpdateArguments,
+        clock::{Clock, ManualClock},
+        identifier::Sequence,
+        test_support::all,
+        wal::Log,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_rows_disappear_once_their_ttl_elapsed() {
+        let clock = ManualClock::default();
+        let db = FakeDb::new(Sequence::new()).with_clock(clock.clone());
+        db.insert_with_ttl("session", Duration::from_secs(30))
+            .unwrap();
+        db.insert("user").unwrap();
+
+        clock.advance(Duration::from_secs(29));
+        assert_eq!(db.find_by_id(&1).unwrap(), Some("session"));
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(db.find_by_id(&1).unwrap(), None);
+        assert_eq!(db.find_many(all()).unwrap(), vec!["user"]);
+        assert!(!db.storage.lock().unwrap().contains_key(&1));
+    }
+
+    /// Log that fails to append while `failing` is set.
+    #[derive(Debug)]
+    struct Flaky {
+        failing: Arc<AtomicBool>,
+    }
+
+    impl<K, V> Log<K, V> for Flaky {
+        fn append(&self, _: &Rows<K, V>, _: Option<u64>, _: &[Change<&K, &V>]) -> io::Result<()> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(io::Error::other("disk full"));
+            }
+            Ok(())
+        }
+
+        fn compact(&self, _: &Rows<K, V>, _: Option<u64>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rows_expire_once_their_removal_is_logged() {
+        let clock = ManualClock::default();
+        let failing = Arc::new(AtomicBool::new(true));
+        let mut db = FakeDb::new(Sequence::new()).with_clock(clock.clone());
+        db.insert_with_ttl("session", Duration::from_secs(30))
+            .unwrap();
+        db.log = Some(Box::new(Flaky {
+            failing: failing.clone(),
+        }));
+
+        clock.advance(Duration::from_secs(30));
+        db.find_by_id(&1).expect_err("the removal is not logged");
+        failing.store(false, Ordering::Relaxed);
+
+        assert_eq!(db.find_by_id(&1).unwrap(), None);
+        assert!(!db.storage.lock().unwrap().contains_key(&1));
+    }
+
+    #[test]
+    fn test_expiry_reads_deadlines_from_values() {
+        let clock = ManualClock::default();
+        let start = clock.now();
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![
+                (1, (Some(start + Duration::from_secs(60)), "cache")),
+                (2, (None, "pinned")),
+            ],
+        )
+        .with_clock(clock.clone())
+        .with_expiry(|(deadline, _)| *deadline);
+
+        clock.advance(Duration::from_secs(60));
+
+        assert_eq!(db.find_by_id(&1).unwrap(), None);
+        assert_eq!(db.find_by_id(&2).unwrap(), Some((None, "pinned")));
+    }
+
+    #[test]
+    fn test_expiry_reads_deadlines_when_rows_are_written() {
+        let clock = ManualClock::default();
+        let start = clock.now();
+        let db = FakeDb::new(Sequence::new())
+            .with_clock(clock.clone())
+            .with_expiry(|(deadline, _): &(Option<SystemTime>, &str)| *deadline);
+        db.insert((Some(start + Duration::from_secs(10)), "cache"))
+            .unwrap();
+        db.insert((Some(start + Duration::from_secs(10)), "token"))
+            .unwrap();
+
+        db.update_many(UpdateArguments {
+            matcher: Box::new(|(_, name): &&(Option<SystemTime>, &str)| *name == "token"),
+            updater: Box::new(move |(deadline, _): &mut (Option<SystemTime>, &str)| {
+                *deadline = Some(start + Duration::from_secs(20));
+            }),
+        })
+        .unwrap();
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(db.find_by_id(&1).unwrap(), None);
+        assert_eq!(
+            db.find_by_id(&2).unwrap().map(|(_, name)| name),
+            Some("token")
+        );
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(db.find_by_id(&2).unwrap(), None);
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Session {
+        pub id: u32,
+    }
+
+    crate::impl_identifier!(SessionId<u32, Session>, id);
+
+    #[test]
+    fn test_rows_inserted_again_do_not_keep_their_ttl() {
+        let clock = ManualClock::default();
+        let db = FakeDb::new(SessionId).with_clock(clock.clone());
+        db.insert_with_ttl(Session { id: 7 }, Duration::from_secs(30))
+            .unwrap();
+
+        db.delete_by_id(&7).unwrap();
+        db.insert(Session { id: 7 }).unwrap();
+        clock.advance(Duration::from_secs(30));
+
+        assert_eq!(db.find_by_id(&7).unwrap(), Some(Session { id: 7 }));
+    }
+
+    #[test]
+    fn test_forks_expire_rows_like_the_forked_db() {
+        let clock = ManualClock::default();
+        let db = FakeDb::new(Sequence::new())
+            .with_clock(clock.clone())
+            .with_expiry(|(deadline, _): &(Option<SystemTime>, &str)| *deadline);
+        db.insert_with_ttl((None, "session"), Duration::from_secs(30))
+            .unwrap();
+
+        let fork = db.fork().unwrap();
+        fork.insert((Some(clock.now() + Duration::from_secs(30)), "cache"))
+            .unwrap();
+        clock.advance(Duration::from_secs(30));
+
+        assert_eq!(fork.find_many(all()).unwrap(), vec![]);
+        assert_eq!(db.find_by_id(&1).unwrap(), None);
+    }
+}
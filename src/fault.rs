@@ -0,0 +1,162 @@
+use std::{fmt, sync::Mutex};
+
+use crate::operation::Operation;
+
+/// A simulated database failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    Timeout,
+    ConnectionReset,
+    SerializationFailure,
+    Other(String),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("timeout"),
+            Self::ConnectionReset => f.write_str("connection reset"),
+            Self::SerializationFailure => f.write_str("serialization failure"),
+            Self::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+pub type FaultPredicate = dyn Fn(&Operation) -> bool + Send + Sync;
+
+enum Trigger {
+    Next(usize),
+    Every { n: usize, calls: usize },
+    When(Box<FaultPredicate>),
+}
+
+impl Trigger {
+    /// Counts the call and returns `true` if it must fail.
+    fn fires(&mut self, operation: &Operation) -> bool {
+        match self {
+            Self::Next(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                true
+            }
+            Self::Next(_) => false,
+            Self::Every { n, calls } => {
+                *calls += 1;
+                *calls % *n == 0
+            }
+            Self::When(predicate) => predicate(operation),
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        matches!(self, Self::Next(0))
+    }
+}
+
+/// Makes FakeDb operations fail with a `Fault` before they touch the storage.
+///
+/// Every rule counts every call, and the fault of the first rule that fires
+/// is returned as a `FakeDbError::Fault`.
+#[derive(Default)]
+pub struct FaultInjector {
+    rules: Mutex<Vec<(Trigger, Fault)>>,
+}
+
+impl FaultInjector {
+    /// Fails the next `n` calls.
+    pub fn fail_next(&self, n: usize, fault: Fault) {
+        self.push(Trigger::Next(n), fault);
+    }
+
+    /// Fails every `n`th call, counting from now.
+    ///
+    /// # Panics
+    /// Panics if `n` is zero
+    pub fn fail_every(&self, n: usize, fault: Fault) {
+        assert!(n > 0, "fail_every needs a period greater than zero");
+        self.push(Trigger::Every { n, calls: 0 }, fault);
+    }
+
+    /// Fails the calls whose operation matches `predicate`.
+    pub fn fail_when<P>(&self, predicate: P, fault: Fault)
+    where
+        P: Fn(&Operation) -> bool + Send + Sync + 'static,
+    {
+        self.push(Trigger::When(Box::new(predicate)), fault);
+    }
+
+    /// Removes every rule.
+    pub fn clear(&self) {
+        self.rules().clear();
+    }
+
+    pub(crate) fn check(&self, operation: Operation) -> Option<Fault> {
+        let mut rules = self.rules();
+        let mut fault = None;
+        for (trigger, rule_fault) in rules.iter_mut() {
+            if trigger.fires(&operation) && fault.is_none() {
+                fault = Some(rule_fault.clone());
+            }
+        }
+        rules.retain(|(trigger, _)| !trigger.is_exhausted());
+
+        fault
+    }
+
+    fn push(&self, trigger: Trigger, fault: Fault) {
+        self.rules().push((trigger, fault));
+    }
+
+    fn rules(&self) -> std::sync::MutexGuard<'_, Vec<(Trigger, Fault)>> {
+        crate::lock(&self.rules)
+    }
+}
+
+impl fmt::Debug for FaultInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjector")
+            .field("rules", &self.rules().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fail_next_fails_only_n_calls() {
+        let faults = FaultInjector::default();
+        faults.fail_next(2, Fault::Timeout);
+
+        assert_eq!(faults.check(Operation::Insert), Some(Fault::Timeout));
+        assert_eq!(faults.check(Operation::FindById), Some(Fault::Timeout));
+        assert_eq!(faults.check(Operation::Insert), None);
+    }
+
+    #[test]
+    fn test_fail_every_fails_each_nth_call() {
+        let faults = FaultInjector::default();
+        faults.fail_every(3, Fault::ConnectionReset);
+
+        let failed: Vec<_> = (0..6)
+            .map(|_| faults.check(Operation::FindMany).is_some())
+            .collect();
+
+        assert_eq!(failed, vec![false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn test_fail_when_fails_matching_operations() {
+        let faults = FaultInjector::default();
+        faults.fail_when(Operation::is_write, Fault::SerializationFailure);
+
+        assert_eq!(faults.check(Operation::FindOne), None);
+        assert_eq!(
+            faults.check(Operation::UpdateMany),
+            Some(Fault::SerializationFailure)
+        );
+
+        faults.clear();
+        assert_eq!(faults.check(Operation::UpdateMany), None);
+    }
+}
@@ -0,0 +1,505 @@
+//! Loading fixture files into FakeDbs, with the `yaml`, `toml` and `csv`
+//! features.
+//!
+//! A fixture file holds values without their keys, which are inserted with
+//! `insert_many` so the identifier generates the keys and the Cardinality and
+//! Conflict checks still run:
+//!  * YAML: a sequence of values
+//!  * TOML: an array of tables named `rows`, written `[[rows]]`
+//!  * CSV: a header naming the fields followed by a record per value
+//!
+//! Errors are wrapped in a Fixture error naming the file and, when it is
+//! known, the line of the value that failed.
+use core::hash::Hash;
+use std::{fs, path::Path};
+
+use serde::de::DeserializeOwned;
+
+use crate::{identifier::FallibleIdentifier, FakeDb, FakeDbError, Operation, Result};
+
+/// Extensions of the fixture files of the enabled formats.
+const EXTENSIONS: &[&str] = &[
+    #[cfg(feature = "yaml")]
+    "yaml",
+    #[cfg(feature = "yaml")]
+    "yml",
+    #[cfg(feature = "toml")]
+    "toml",
+    #[cfg(feature = "csv")]
+    "csv",
+];
+
+/// Values parsed from a fixture file, with the line each of them starts at.
+struct Parsed<V> {
+    values: Vec<V>,
+    lines: Vec<usize>,
+}
+
+/// Error while parsing a fixture file, at `line` when it is known.
+struct ParseError {
+    line: Option<usize>,
+    message: String,
+}
+
+type Parser<V> = fn(&str) -> std::result::Result<Parsed<V>, ParseError>;
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Inserts the values of a YAML file holding a sequence of them.
+    ///
+    /// # Errors
+    /// Returns a Fixture error wrapping the Io error of an unreadable file,
+    /// the Format error of an invalid one, or the error of `insert_many`
+    #[cfg(feature = "yaml")]
+    pub fn load_yaml<P: AsRef<Path>>(&self, path: P) -> Result<Vec<K>>
+    where
+        V: DeserializeOwned,
+    {
+        self.load_with(path.as_ref(), parse_yaml)
+    }
+
+    /// Inserts the values of a TOML file holding them in a `[[rows]]` array.
+    ///
+    /// # Errors
+    /// Returns a Fixture error wrapping the Io error of an unreadable file,
+    /// the Format error of an invalid one, or the error of `insert_many`
+    #[cfg(feature = "toml")]
+    pub fn load_toml<P: AsRef<Path>>(&self, path: P) -> Result<Vec<K>>
+    where
+        V: DeserializeOwned,
+    {
+        self.load_with(path.as_ref(), parse_toml)
+    }
+
+    /// Inserts the values of a CSV file with headers.
+    ///
+    /// # Errors
+    /// Returns a Fixture error wrapping the Io error of an unreadable file,
+    /// the Format error of an invalid one, or the error of `insert_many`
+    #[cfg(feature = "csv")]
+    pub fn load_csv<P: AsRef<Path>>(&self, path: P) -> Result<Vec<K>>
+    where
+        V: DeserializeOwned,
+    {
+        self.load_with(path.as_ref(), parse_csv)
+    }
+
+    /// Inserts the values of a fixture file in the format of its extension.
+    ///
+    /// # Errors
+    /// Returns a Fixture error wrapping the Io error of an unreadable file,
+    /// the Format error of an invalid one or of an extension of no enabled
+    /// format, or the error of `insert_many`
+    pub fn load_fixture<P: AsRef<Path>>(&self, path: P) -> Result<Vec<K>>
+    where
+        V: DeserializeOwned,
+    {
+        let path = path.as_ref();
+        match parser(path) {
+            Some(parse) => self.load_with(path, parse),
+            None => Err(fixture_error(
+                path,
+                None,
+                self.context(Operation::Load)
+                    .format("extension of no enabled fixture format"),
+            )),
+        }
+    }
+
+    fn load_with(&self, path: &Path, parse: Parser<V>) -> Result<Vec<K>> {
+        let context = self.context(Operation::Load);
+        let text =
+            fs::read_to_string(path).map_err(|err| fixture_error(path, None, context.io(err)))?;
+        let Parsed { values, lines } = parse(&text)
+            .map_err(|err| fixture_error(path, err.line, context.format(err.message)))?;
+
+        let keys = self.fixture_keys(&values);
+        self.insert_many(values).map_err(|err| {
+            let line = failed_row(&err, &keys).and_then(|row| lines.get(row).copied());
+            fixture_error(path, line, err)
+        })
+    }
+
+    /// Keys of `values`, to find the value an insert failed on. Only known
+    /// for identifiers deriving keys from the value, as generating a key may
+    /// otherwise consume it.
+    fn fixture_keys(&self, values: &[V]) -> Vec<Option<String>> {
+        if !self.identifier.is_autogenerated() {
+            return Vec::new();
+        }
+        values
+            .iter()
+            .map(|value| {
+                let id = self.identifier.try_new_id(value).ok();
+                id.map(|id| format!("{id:?}"))
+            })
+            .collect()
+    }
+}
+
+/// Index of the value `err` was returned for, given the keys of the values.
+fn failed_row(err: &FakeDbError, keys: &[Option<String>]) -> Option<usize> {
+    let mut rows = keys.iter().enumerate();
+    match err {
+        FakeDbError::InvalidId { .. } => rows.find(|(_, id)| id.is_none()),
+        FakeDbError::Conflict { key, .. } => rows.find(|(_, id)| id.as_ref() == Some(key)),
+        // The first value with the key is fine, the repeated one is not.
+        FakeDbError::Cardinality { key, .. } => {
+            rows.filter(|(_, id)| id.as_ref() == Some(key)).nth(1)
+        }
+        _ => None,
+    }
+    .map(|(row, _)| row)
+}
+
+fn fixture_error(path: &Path, line: Option<usize>, error: FakeDbError) -> FakeDbError {
+    FakeDbError::Fixture {
+        path: path.display().to_string(),
+        line,
+        error: Box::new(error),
+    }
+}
+
+fn parser<V: DeserializeOwned>(path: &Path) -> Option<Parser<V>> {
+    match path.extension()?.to_str()? {
+        #[cfg(feature = "yaml")]
+        "yaml" | "yml" => Some(parse_yaml),
+        #[cfg(feature = "toml")]
+        "toml" => Some(parse_toml),
+        #[cfg(feature = "csv")]
+        "csv" => Some(parse_csv),
+        _ => None,
+    }
+}
+
+/// Numbers, from 1, of the lines where `starts_row` is true.
+#[cfg(any(feature = "yaml", feature = "toml"))]
+fn row_lines<F: Fn(&str) -> bool>(text: &str, starts_row: F) -> Vec<usize> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| starts_row(line))
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+#[cfg(feature = "yaml")]
+fn parse_yaml<V: DeserializeOwned>(text: &str) -> std::result::Result<Parsed<V>, ParseError> {
+    let values = serde_yaml::from_str(text).map_err(|err| ParseError {
+        line: err.location().map(|location| location.line()),
+        message: err.to_string(),
+    })?;
+    // Items of a block sequence start with a dash on the first column.
+    let lines = row_lines(text, |line| line == "-" || line.starts_with("- "));
+
+    Ok(Parsed { values, lines })
+}
+
+#[cfg(feature = "toml")]
+fn parse_toml<V: DeserializeOwned>(text: &str) -> std::result::Result<Parsed<V>, ParseError> {
+    #[derive(serde::Deserialize)]
+    struct Rows<V> {
+        rows: Vec<V>,
+    }
+
+    let Rows { rows: values } = toml::from_str(text).map_err(|err| ParseError {
+        line: err
+            .span()
+            .map(|span| text[..span.start].matches('\n').count() + 1),
+        message: err.message().to_owned(),
+    })?;
+    let lines = row_lines(text, |line| line.trim() == "[[rows]]");
+
+    Ok(Parsed { values, lines })
+}
+
+#[cfg(feature = "csv")]
+fn parse_csv<V: DeserializeOwned>(text: &str) -> std::result::Result<Parsed<V>, ParseError> {
+    let csv_error = |err: csv::Error| ParseError {
+        line: err.position().map(|position| position.line() as usize),
+        message: err.to_string(),
+    };
+
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let mut parsed = Parsed {
+        values: Vec::new(),
+        lines: Vec::new(),
+    };
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let line = record
+            .position()
+            .map_or(0, |position| position.line() as usize);
+        let value = record
+            .deserialize(Some(&headers))
+            .map_err(|err| ParseError {
+                line: Some(line),
+                message: err.to_string(),
+            })?;
+        parsed.values.push(value);
+        parsed.lines.push(line);
+    }
+
+    Ok(parsed)
+}
+
+/// A FakeDb that `Fixtures` fills from the file named after its table.
+pub trait FixtureTable {
+    fn table_name(&self) -> &str;
+
+    /// Inserts the values of the fixture file at `path`, returning how many
+    /// were inserted.
+    ///
+    /// # Errors
+    /// See `FakeDb::load_fixture`
+    fn load_file(&self, path: &Path) -> Result<usize>;
+}
+
+impl<K, V, I> FixtureTable for FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone + DeserializeOwned,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    fn table_name(&self) -> &str {
+        self.name()
+    }
+
+    fn load_file(&self, path: &Path) -> Result<usize> {
+        self.load_fixture(path).map(|ids| ids.len())
+    }
+}
+
+/// Fills several FakeDbs from the fixture files of a directory.
+///
+/// ```ignore
+/// Fixtures::new()
+///     .table(&countries)
+///     .table(&cities)
+///     .load_dir("tests/fixtures")?;
+/// ```
+#[derive(Default)]
+pub struct Fixtures<'a> {
+    tables: Vec<&'a dyn FixtureTable>,
+}
+
+impl<'a> Fixtures<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn table(mut self, table: &'a dyn FixtureTable) -> Self {
+        self.tables.push(table);
+        self
+    }
+
+    /// Loads the file of each table, in the order the tables were added. The
+    /// file of a table is named after it, with the extension of an enabled
+    /// format, like `countries.csv`.
+    ///
+    /// Tables without a file are left unchanged and files of no table are
+    /// ignored.
+    ///
+    /// # Errors
+    ///  * A table with more than one file, like `countries.csv` and
+    ///    `countries.yaml`, results in a Fixture error naming both
+    ///  * Stops at the first file that fails to load, see
+    ///    `FakeDb::load_fixture`
+    pub fn load_dir<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        for table in &self.tables {
+            let name = table.table_name();
+            let mut paths = EXTENSIONS
+                .iter()
+                .map(|extension| dir.as_ref().join(format!("{name}.{extension}")))
+                .filter(|path| path.is_file());
+            let Some(path) = paths.next() else {
+                continue;
+            };
+            if let Some(other) = paths.next() {
+                let error = FakeDbError::Format {
+                    operation: Operation::Load,
+                    table: name.to_owned(),
+                    message: format!("{} is a fixture of the same table", other.display()),
+                };
+                return Err(fixture_error(&path, None, error));
+            }
+            table.load_file(&path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde::Deserialize;
+    use tempfile::TempDir;
+
+    use crate::impl_identifier;
+
+    use super::*;
+
+    #[derive(Clone, Deserialize)]
+    pub struct Country {
+        pub id: u32,
+        pub name: String,
+    }
+
+    impl_identifier!(CountryId<u32, Country>, id);
+
+    /// Writes `contents` to a file of `dir`.
+    fn fixture(dir: &TempDir, file: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(file);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn fixture_line(err: &FakeDbError) -> Option<usize> {
+        match err {
+            FakeDbError::Fixture { line, .. } => *line,
+            _ => panic!("expected a fixture error, found {err:?}"),
+        }
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_load_yaml_inserts_values() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(
+            &dir,
+            "countries.yaml",
+            "- id: 7\n  name: Laos\n- id: 95\n  name: Myanmar\n",
+        );
+        let db = FakeDb::new(CountryId);
+
+        let ids = db.load_yaml(&path).unwrap();
+
+        assert_eq!(ids, vec![7, 95]);
+        assert_eq!(db.find_by_id(&95).unwrap().unwrap().name, "Myanmar");
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_load_yaml_points_at_repeated_value() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(
+            &dir,
+            "countries.yaml",
+            "- id: 7\n  name: Laos\n- id: 7\n  name: Laos\n",
+        );
+        let db = FakeDb::new(CountryId).with_name("countries");
+
+        let err = db.load_yaml(&path).expect_err("id 7 is repeated");
+
+        assert_eq!(fixture_line(&err), Some(3));
+        assert!(err
+            .to_string()
+            .ends_with("countries.yaml:3: insert_many on countries: key 7 is repeated"));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_load_toml_points_at_invalid_value() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(
+            &dir,
+            "countries.toml",
+            "[[rows]]\nid = 7\nname = \"Laos\"\n\n[[rows]]\nid = \"95\"\nname = \"Myanmar\"\n",
+        );
+        let db = FakeDb::new(CountryId);
+
+        let err = db.load_toml(&path).expect_err("id is not a number");
+
+        assert_eq!(fixture_line(&err), Some(6));
+        assert_eq!(db.find_by_id(&7).unwrap().map(|c| c.name), None);
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_load_csv_points_at_conflicting_record() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "countries.csv", "id,name\n856,Laos\n95,Myanmar\n");
+        let db = FakeDb::new(CountryId);
+        db.insert(Country {
+            id: 95,
+            name: "Burma".into(),
+        })
+        .unwrap();
+
+        let err = db.load_csv(&path).expect_err("id 95 is in storage");
+
+        assert_eq!(fixture_line(&err), Some(3));
+        assert_eq!(db.find_by_id(&95).unwrap().unwrap().name, "Burma");
+        assert!(matches!(
+            err,
+            FakeDbError::Fixture { error, .. } if matches!(*error, FakeDbError::Conflict { .. })
+        ));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_fixtures_load_every_table_of_a_dir() {
+        let dir = TempDir::new().unwrap();
+        fixture(&dir, "countries.csv", "id,name\n856,Laos\n");
+        fixture(&dir, "capitals.csv", "id,name\n1,Vientiane\n2,Naypyidaw\n");
+        let countries = FakeDb::new(CountryId).with_name("countries");
+        let capitals = FakeDb::new(CountryId).with_name("capitals");
+        let empty = FakeDb::new(CountryId).with_name("cities");
+
+        Fixtures::new()
+            .table(&countries)
+            .table(&capitals)
+            .table(&empty)
+            .load_dir(dir.path())
+            .unwrap();
+
+        assert!(countries.find_by_id(&856).unwrap().is_some());
+        assert_eq!(capitals.find_many(Default::default()).unwrap().len(), 2);
+        assert!(empty.find_many(Default::default()).unwrap().is_empty());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_fixtures_reject_two_files_of_a_table() {
+        let dir = TempDir::new().unwrap();
+        let yml = fixture(&dir, "countries.yml", "- id: 7\n  name: Laos\n");
+        let yaml = fixture(&dir, "countries.yaml", "- id: 7\n  name: Laos\n");
+        let countries = FakeDb::new(CountryId).with_name("countries");
+
+        let err = Fixtures::new()
+            .table(&countries)
+            .load_dir(dir.path())
+            .expect_err("countries has two files");
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}: load on countries: invalid data, {} is a fixture of the same table",
+                yaml.display(),
+                yml.display()
+            )
+        );
+        assert!(countries.find_by_id(&7).unwrap().is_none());
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn test_load_fixture_reports_unreadable_file_as_io() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("countries.csv");
+        let db = FakeDb::new(CountryId);
+
+        let err = db.load_fixture(&path).expect_err("the file does not exist");
+
+        assert!(matches!(
+            err,
+            FakeDbError::Fixture { error, .. } if matches!(*error, FakeDbError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,434 @@
+//! Foreign keys between FakeDbs, enforced through their hooks.
+//!
+//! The hooks of a foreign key hold weak references to the other FakeDb, so
+//! tables referencing each other are still dropped, and a foreign key stops
+//! being enforced once the other FakeDb is dropped.
+//!
+//! Hooks run while the storage of their FakeDb is locked, and lock the other
+//! FakeDb. So that a thread never waits for a storage locked by a thread
+//! waiting for its own, FakeDbs connected by foreign keys are locked by one
+//! thread at a time. A FakeDb can not reference itself.
+use core::hash::Hash;
+use std::{
+    marker::PhantomData,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
+};
+
+use crate::{
+    args::UpdateArguments, errors::Rejection, identifier::FallibleIdentifier, FakeDb, Operation,
+    Result,
+};
+
+/// Lock shared by the FakeDbs connected by foreign keys, taken before their
+/// storage.
+///
+/// A write to a child locks its storage and then the storage of the parent
+/// to check the reference, while a delete from the parent locks them the
+/// other way around, so both threads could wait for each other forever.
+/// Holding this lock first leaves one of them waiting before it locks any
+/// storage. It is reentrant, as hooks lock the other FakeDb from the thread
+/// already holding it.
+///
+/// Each FakeDb starts with its own lock. A foreign key merges the locks of
+/// its two FakeDbs, so FakeDbs that are not connected are locked apart.
+#[derive(Default)]
+pub(crate) struct Relations {
+    /// Thread holding the lock, and how many times it took it.
+    owner: Mutex<Option<(ThreadId, usize)>>,
+    released: Condvar,
+    /// Lock this one was merged into, which is taken instead.
+    merged: Mutex<Option<Arc<Relations>>>,
+}
+
+/// Releases the lock of `Relations` once dropped, from the thread that took
+/// it.
+pub(crate) struct RelationGuard {
+    relations: Arc<Relations>,
+    _thread: PhantomData<*const ()>,
+}
+
+impl Relations {
+    /// Takes the lock this one was merged into, if any, or else this one.
+    pub fn enter(self: &Arc<Self>) -> RelationGuard {
+        loop {
+            let relations = self.root();
+            let thread = thread::current().id();
+            let mut owner = crate::lock(&relations.owner);
+            loop {
+                match &mut *owner {
+                    Some((holder, depth)) if *holder == thread => *depth += 1,
+                    Some(_) => {
+                        owner = relations
+                            .released
+                            .wait(owner)
+                            .unwrap_or_else(|err| err.into_inner());
+                        continue;
+                    }
+                    None => *owner = Some((thread, 1)),
+                }
+                break;
+            }
+            drop(owner);
+            let guard = RelationGuard {
+                relations,
+                _thread: PhantomData,
+            };
+            // Merged while waiting for it, so the merged lock is taken.
+            if crate::lock(&guard.relations.merged).is_none() {
+                return guard;
+            }
+        }
+    }
+
+    /// Merges the locks of `a` and `b`, which are held meanwhile.
+    fn merge(a: &Arc<Self>, b: &Arc<Self>) {
+        let (a, b) = (a.enter(), b.enter());
+        if !Arc::ptr_eq(&a.relations, &b.relations) {
+            *crate::lock(&a.relations.merged) = Some(b.relations.clone());
+        }
+    }
+
+    fn root(self: &Arc<Self>) -> Arc<Self> {
+        let mut relations = self.clone();
+        loop {
+            let merged = crate::lock(&relations.merged).clone();
+            match merged {
+                Some(merged) => relations = merged,
+                None => return relations,
+            }
+        }
+    }
+}
+
+impl Drop for RelationGuard {
+    fn drop(&mut self) {
+        let mut owner = crate::lock(&self.relations.owner);
+        if let Some((_, depth)) = &mut *owner {
+            *depth -= 1;
+            if *depth == 0 {
+                *owner = None;
+                self.relations.released.notify_one();
+            }
+        }
+    }
+}
+
+/// What deleting a referenced row does to the rows referencing it.
+pub enum OnDelete<V> {
+    /// Rejects the delete while rows reference the deleted row.
+    Restrict,
+    /// Deletes the rows referencing the deleted row.
+    Cascade,
+    /// Updates the rows referencing the deleted row with the setter, which
+    /// clears the referencing field.
+    SetNull(Box<dyn Fn(&mut V) + Send + Sync>),
+}
+
+impl<V> OnDelete<V> {
+    pub fn set_null<S: Fn(&mut V) + Send + Sync + 'static>(clear: S) -> Self {
+        Self::SetNull(Box::new(clear))
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    I: FallibleIdentifier<V, Id = K> + Send + Sync + 'static,
+{
+    /// Declares that the key `column` returns for a row of this FakeDb
+    /// references a row of `parent`, like a nullable foreign key column:
+    ///  * inserts and updates of rows whose key is not in `parent` are
+    ///    rejected
+    ///  * deleting a row of `parent` does what `on_delete` says to the rows
+    ///    referencing it, through the operations of this FakeDb
+    ///
+    /// Soft deleted and expired rows do not count as stored.
+    ///
+    /// # Panics
+    /// Panics if `parent` is this FakeDb, whose hooks would wait for the
+    /// storage locked by the operation running them
+    pub fn references<PK, PV, PI, C>(
+        self: &Arc<Self>,
+        parent: &Arc<FakeDb<PK, PV, PI>>,
+        column: C,
+        on_delete: OnDelete<V>,
+    ) where
+        PK: Eq + Hash + std::fmt::Debug + Clone + Send + Sync + 'static,
+        PV: Clone + Send + Sync + 'static,
+        PI: FallibleIdentifier<PV, Id = PK> + Send + Sync + 'static,
+        C: Fn(&V) -> Option<PK> + Send + Sync + 'static,
+    {
+        assert!(
+            !std::ptr::eq(Arc::as_ptr(self).cast::<()>(), Arc::as_ptr(parent).cast()),
+            "{} can not reference itself",
+            self.name()
+        );
+        Relations::merge(
+            self.relations.get_or_init(Arc::default),
+            parent.relations.get_or_init(Arc::default),
+        );
+        let column = Arc::new(column);
+
+        let check = {
+            let (parent, column) = (Arc::downgrade(parent), column.clone());
+            move |value: &V| match (column(value), parent.upgrade()) {
+                (Some(key), Some(parent)) => match parent.stores(&key) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(Rejection::new(format!(
+                        "{key:?} not found in {}",
+                        parent.name()
+                    ))),
+                    Err(err) => Err(Rejection::new(err.to_string())),
+                },
+                _ => Ok(()),
+            }
+        };
+        let check = Arc::new(check);
+        let on_insert = check.clone();
+        self.hooks().after_insert(move |_, value| on_insert(value));
+        self.hooks().after_update(move |_, _, value| check(value));
+
+        let child = Arc::downgrade(self);
+        let references = move |key: &PK| {
+            let column = column.clone();
+            let key = key.clone();
+            move |value: &&V| column(value).as_ref() == Some(&key)
+        };
+        match on_delete {
+            OnDelete::Restrict => parent.hooks().before_delete(move |key, _| {
+                let Some(child) = child.upgrade() else {
+                    return Ok(());
+                };
+                match child.any_row(references(key)) {
+                    Ok(false) => Ok(()),
+                    Ok(true) => Err(Rejection::new(format!("referenced by {}", child.name()))),
+                    Err(err) => Err(Rejection::new(err.to_string())),
+                }
+            }),
+            OnDelete::Cascade => parent.hooks().after_delete(move |key, _| {
+                let Some(child) = child.upgrade() else {
+                    return Ok(());
+                };
+                child
+                    .delete_many(references(key))
+                    .map(|_| ())
+                    .map_err(|err| Rejection::new(err.to_string()))
+            }),
+            OnDelete::SetNull(clear) => {
+                let clear = Arc::new(clear);
+                parent.hooks().after_delete(move |key, _| {
+                    let Some(child) = child.upgrade() else {
+                        return Ok(());
+                    };
+                    let clear = clear.clone();
+                    child
+                        .update_many(UpdateArguments {
+                            matcher: Box::new(references(key)),
+                            updater: Box::new(move |value| clear(value)),
+                        })
+                        .map_err(|err| Rejection::new(err.to_string()))
+                })
+            }
+        }
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Returns `true` if a row of `key` is stored, without journaling.
+    fn stores(&self, key: &K) -> Result<bool> {
+        let storage = self.lock_storage(Operation::FindById)?;
+        Ok(storage
+            .get(key)
+            .is_some_and(|value| !self.is_deleted(value)))
+    }
+
+    /// Returns `true` if a stored row matches, without journaling.
+    fn any_row<M: FnMut(&&V) -> bool>(&self, mut matcher: M) -> Result<bool> {
+        let storage = self.lock_storage(Operation::FindMany)?;
+        Ok(storage
+            .values()
+            .any(|value| !self.is_deleted(value) && matcher(&value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use crate::{identifier::Sequence, test_support::all, FakeDbError};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct City {
+        name: &'static str,
+        country: Option<u32>,
+    }
+
+    type Tables = (
+        Arc<FakeDb<u32, &'static str, Sequence>>,
+        Arc<FakeDb<u32, City, Sequence>>,
+    );
+
+    fn tables(on_delete: OnDelete<City>) -> Tables {
+        let countries = Arc::new(
+            FakeDb::seeded(Sequence::new(), vec![(1, "Peru"), (2, "Chile")]).with_name("countries"),
+        );
+        let cities = Arc::new(
+            FakeDb::seeded(
+                Sequence::new(),
+                vec![(
+                    1,
+                    City {
+                        name: "Lima",
+                        country: Some(1),
+                    },
+                )],
+            )
+            .with_name("cities"),
+        );
+        cities.references(&countries, |city| city.country, on_delete);
+        (countries, cities)
+    }
+
+    #[test]
+    fn test_rows_must_reference_stored_keys() {
+        let (_countries, cities) = tables(OnDelete::Restrict);
+
+        let err = cities
+            .insert(City {
+                name: "Quito",
+                country: Some(3),
+            })
+            .expect_err("country 3 is not stored");
+        cities
+            .insert(City {
+                name: "Atlantis",
+                country: None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            err,
+            FakeDbError::Rejected {
+                operation: Operation::Insert,
+                table: "cities".into(),
+                key: "2".into(),
+                reason: "3 not found in countries".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_restrict_rejects_deleting_referenced_rows() {
+        let (countries, _cities) = tables(OnDelete::Restrict);
+
+        let err = countries
+            .delete_by_id(&1)
+            .expect_err("Lima references Peru");
+        countries.delete_by_id(&2).unwrap();
+
+        assert!(err.to_string().ends_with("rejected, referenced by cities"));
+        assert_eq!(countries.find_by_id(&1).unwrap(), Some("Peru"));
+    }
+
+    #[test]
+    fn test_cascade_deletes_referencing_rows() {
+        let (countries, cities) = tables(OnDelete::Cascade);
+
+        countries.delete_by_id(&1).unwrap();
+
+        assert_eq!(cities.find_by_id(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_null_clears_references() {
+        let (countries, cities) = tables(OnDelete::set_null(|city: &mut City| city.country = None));
+
+        countries.delete_many(|_| true).unwrap();
+
+        assert_eq!(cities.find_by_id(&1).unwrap().unwrap().country, None);
+    }
+
+    #[test]
+    fn test_related_tables_can_be_written_concurrently() {
+        let (countries, cities) = tables(OnDelete::Restrict);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    cities
+                        .insert(City {
+                            name: "Cusco",
+                            country: Some(1),
+                        })
+                        .unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..1000 {
+                    countries
+                        .delete_by_id(&1)
+                        .expect_err("Lima references Peru");
+                }
+            });
+        });
+
+        assert_eq!(cities.find_many(all()).unwrap().len(), 1001);
+    }
+
+    #[test]
+    fn test_unrelated_tables_are_locked_apart() {
+        let (_countries, cities) = tables(OnDelete::Restrict);
+        let (_regions, towns) = tables(OnDelete::Restrict);
+        // Inserts a town from another thread while the cities are locked.
+        cities.hooks().before_insert(move |_, _| {
+            let (sender, receiver) = mpsc::channel();
+            let towns = towns.clone();
+            std::thread::spawn(move || {
+                let town = towns.insert(City {
+                    name: "Cusco",
+                    country: Some(1),
+                });
+                sender.send(town).unwrap();
+            });
+            match receiver.recv_timeout(Duration::from_secs(5)) {
+                Ok(Ok(_)) => Ok(()),
+                _ => Err(Rejection::new("the town was not inserted")),
+            }
+        });
+
+        cities
+            .insert(City {
+                name: "Arequipa",
+                country: Some(1),
+            })
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "cities can not reference itself")]
+    fn test_tables_can_not_reference_themselves() {
+        let (_countries, cities) = tables(OnDelete::Restrict);
+
+        cities.references(&cities, |city| city.country, OnDelete::Restrict);
+    }
+
+    #[test]
+    fn test_tables_are_dropped_despite_references() {
+        let (countries, cities) = tables(OnDelete::Cascade);
+        let weak = Arc::downgrade(&cities);
+
+        drop(cities);
+
+        assert!(weak.upgrade().is_none());
+        countries.delete_by_id(&1).unwrap();
+    }
+}
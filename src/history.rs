@@ -0,0 +1,243 @@
+//! Versions of every row of a FakeDb, kept only when it is created
+//! `with_history`, to query rows as they were.
+use core::hash::Hash;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use crate::{
+    identifier::FallibleIdentifier,
+    observer::{Batch, Event},
+    FakeDb,
+};
+
+/// A row as it was written by an operation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Version<V> {
+    /// Starts at 1 for the first write of the key and grows by one with
+    /// every write, across deletes.
+    pub version: u64,
+    pub at: SystemTime,
+    /// `None` when the row was deleted.
+    pub value: Option<V>,
+}
+
+/// The point in the history of a row to read it at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    Time(SystemTime),
+    Version(u64),
+}
+
+impl From<SystemTime> for AsOf {
+    fn from(time: SystemTime) -> Self {
+        Self::Time(time)
+    }
+}
+
+impl From<u64> for AsOf {
+    fn from(version: u64) -> Self {
+        Self::Version(version)
+    }
+}
+
+pub(crate) struct History<K, V> {
+    enabled: bool,
+    rows: Mutex<HashMap<K, Vec<Version<V>>>>,
+}
+
+impl<K, V> Default for History<K, V> {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rows: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> History<K, V> {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Appends a version with `value` to the history of each key.
+    pub fn record<'a, C>(&self, changes: C, at: SystemTime)
+    where
+        C: IntoIterator<Item = (&'a K, Option<&'a V>)>,
+        K: 'a,
+        V: 'a,
+    {
+        let mut rows = self.rows();
+        for (key, value) in changes {
+            let versions = rows.entry(key.clone()).or_default();
+            versions.push(Version {
+                version: versions.last().map_or(1, |last| last.version + 1),
+                at,
+                value: value.cloned(),
+            });
+        }
+    }
+
+    fn rows(&self) -> MutexGuard<'_, HashMap<K, Vec<Version<V>>>> {
+        crate::lock(&self.rows)
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Keeps every version of every row written by an insert, update or
+    /// delete, timestamped by the clock. Rows already stored start at
+    /// version 1, and expired rows end with a deleted version, while rows
+    /// replaced by `restore` are not recorded. Call it after `with_clock`,
+    /// so stored rows are timestamped by the right clock.
+    pub fn with_history(mut self) -> Self {
+        self.history = History {
+            enabled: true,
+            ..History::default()
+        };
+        let rows = self
+            .storage
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner());
+        let now = self.clock.now();
+        self.history
+            .record(rows.iter().map(|(key, value)| (key, Some(value))), now);
+        self
+    }
+
+    /// Versions of the row of `id`, oldest first.
+    ///
+    /// # Panics
+    /// Panics if the FakeDb was not created `with_history`
+    pub fn history(&self, id: &K) -> Vec<Version<V>> {
+        assert!(
+            self.history.is_enabled(),
+            "history of {} is not recorded, create it with_history",
+            self.name()
+        );
+        self.history.rows().get(id).cloned().unwrap_or_default()
+    }
+
+    /// The row of `id` as it was at a time or version, or `None` if it was
+    /// not stored yet or deleted then:
+    ///
+    /// ```ignore
+    /// db.find_by_id_as_of(&1, 2);
+    /// db.find_by_id_as_of(&1, SystemTime::UNIX_EPOCH + Duration::from_secs(60));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the FakeDb was not created `with_history`
+    pub fn find_by_id_as_of<A: Into<AsOf>>(&self, id: &K, as_of: A) -> Option<V> {
+        let as_of = as_of.into();
+        self.history(id)
+            .into_iter()
+            .take_while(|version| match as_of {
+                AsOf::Time(time) => version.at <= time,
+                AsOf::Version(number) => version.version <= number,
+            })
+            .last()
+            .and_then(|version| version.value)
+    }
+
+    /// Records the events of `batch` in the history, once the operation
+    /// wrote them to storage.
+    pub(crate) fn record_history(&self, batch: &Batch<K, V>) {
+        if !self.history.is_enabled() {
+            return;
+        }
+        let changes = batch.events().iter().map(|event| match event {
+            Event::Inserted { key, value }
+            | Event::Updated {
+                key, new: value, ..
+            } => (key, Some(value)),
+            Event::Deleted { key, .. } => (key, None),
+        });
+        self.history.record(changes, self.clock.now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{args::UpdateArguments, clock::ManualClock, identifier::Sequence};
+
+    use super::*;
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn rename(db: &FakeDb<u32, &'static str, Sequence>, from: &'static str, to: &'static str) {
+        db.update_many(UpdateArguments {
+            matcher: Box::new(move |name: &&&str| **name == from),
+            updater: Box::new(move |name: &mut &str| *name = to),
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_history_lists_every_version() {
+        let clock = ManualClock::default();
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Siam")])
+            .with_clock(clock.clone())
+            .with_history();
+
+        clock.advance(Duration::from_secs(10));
+        rename(&db, "Siam", "Thailand");
+        clock.advance(Duration::from_secs(10));
+        db.delete_by_id(&1).unwrap();
+
+        assert_eq!(
+            db.history(&1),
+            vec![
+                Version {
+                    version: 1,
+                    at: at(0),
+                    value: Some("Siam")
+                },
+                Version {
+                    version: 2,
+                    at: at(10),
+                    value: Some("Thailand")
+                },
+                Version {
+                    version: 3,
+                    at: at(20),
+                    value: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_by_id_as_of_reads_past_versions() {
+        let clock = ManualClock::default();
+        let db = FakeDb::new(Sequence::new())
+            .with_clock(clock.clone())
+            .with_history();
+        clock.advance(Duration::from_secs(5));
+        db.insert("Ceylon").unwrap();
+        clock.advance(Duration::from_secs(60));
+        rename(&db, "Ceylon", "Sri Lanka");
+
+        assert_eq!(db.find_by_id_as_of(&1, at(4)), None);
+        assert_eq!(db.find_by_id_as_of(&1, at(64)), Some("Ceylon"));
+        assert_eq!(db.find_by_id_as_of(&1, at(65)), Some("Sri Lanka"));
+        assert_eq!(db.find_by_id_as_of(&1, 1), Some("Ceylon"));
+        assert_eq!(db.find_by_id_as_of(&2, 1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "create it with_history")]
+    fn test_history_requires_with_history() {
+        FakeDb::<u32, &str, Sequence>::default().history(&1);
+    }
+}
@@ -0,0 +1,343 @@
+//! Hooks run by the write operations of a FakeDb, like database triggers
+//! and CHECK constraints.
+//!
+//! Hooks run while the storage is locked:
+//!  * before hooks run first and may change the value being written
+//!  * after hooks run once every before hook of the operation ran and every
+//!    check passed, right before the operation is committed
+//!
+//! A hook returning a `Rejection` aborts the operation with a Rejected
+//! error. Hooks may write to other FakeDbs, to cascade changes, but not to
+//! the FakeDb running them, whose storage is locked. Changes made to other
+//! FakeDbs are not undone when the operation fails afterwards.
+use core::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{errors::Rejection, identifier::FallibleIdentifier, FakeDb, Operation, Result};
+
+type HookResult = std::result::Result<(), Rejection>;
+type WriteHook<K, V> = dyn Fn(&K, &mut V) -> HookResult + Send + Sync;
+type RowHook<K, V> = dyn Fn(&K, &V) -> HookResult + Send + Sync;
+type UpdateHook<K, V> = dyn Fn(&K, &V, &mut V) -> HookResult + Send + Sync;
+type AfterUpdateHook<K, V> = dyn Fn(&K, &V, &V) -> HookResult + Send + Sync;
+type RowHooks<K, V> = Vec<Arc<RowHook<K, V>>>;
+
+struct Registered<K, V> {
+    before_insert: Vec<Arc<WriteHook<K, V>>>,
+    after_insert: RowHooks<K, V>,
+    before_update: Vec<Arc<UpdateHook<K, V>>>,
+    after_update: Vec<Arc<AfterUpdateHook<K, V>>>,
+    before_delete: RowHooks<K, V>,
+    after_delete: RowHooks<K, V>,
+}
+
+impl<K, V> Default for Registered<K, V> {
+    fn default() -> Self {
+        Self {
+            before_insert: Vec::new(),
+            after_insert: Vec::new(),
+            before_update: Vec::new(),
+            after_update: Vec::new(),
+            before_delete: Vec::new(),
+            after_delete: Vec::new(),
+        }
+    }
+}
+
+/// Hooks of the write operations of a FakeDb, returned by `FakeDb::hooks`.
+pub struct Hooks<K, V> {
+    registered: Mutex<Registered<K, V>>,
+}
+
+impl<K, V> Default for Hooks<K, V> {
+    fn default() -> Self {
+        Self {
+            registered: Mutex::new(Registered::default()),
+        }
+    }
+}
+
+impl<K, V> Hooks<K, V> {
+    /// Runs `hook` with the key and value of every inserted row, before it
+    /// is stored. The key is generated before the hook runs.
+    pub fn before_insert<H>(&self, hook: H)
+    where
+        H: Fn(&K, &mut V) -> HookResult + Send + Sync + 'static,
+    {
+        self.registered().before_insert.push(Arc::new(hook));
+    }
+
+    pub fn after_insert<H>(&self, hook: H)
+    where
+        H: Fn(&K, &V) -> HookResult + Send + Sync + 'static,
+    {
+        self.registered().after_insert.push(Arc::new(hook));
+    }
+
+    /// Runs `hook` with the key, stored value and new value of every updated
+    /// row, before it is stored.
+    pub fn before_update<H>(&self, hook: H)
+    where
+        H: Fn(&K, &V, &mut V) -> HookResult + Send + Sync + 'static,
+    {
+        self.registered().before_update.push(Arc::new(hook));
+    }
+
+    pub fn after_update<H>(&self, hook: H)
+    where
+        H: Fn(&K, &V, &V) -> HookResult + Send + Sync + 'static,
+    {
+        self.registered().after_update.push(Arc::new(hook));
+    }
+
+    pub fn before_delete<H>(&self, hook: H)
+    where
+        H: Fn(&K, &V) -> HookResult + Send + Sync + 'static,
+    {
+        self.registered().before_delete.push(Arc::new(hook));
+    }
+
+    pub fn after_delete<H>(&self, hook: H)
+    where
+        H: Fn(&K, &V) -> HookResult + Send + Sync + 'static,
+    {
+        self.registered().after_delete.push(Arc::new(hook));
+    }
+
+    /// Removes every hook.
+    pub fn clear(&self) {
+        *self.registered() = Registered::default();
+    }
+
+    fn registered(&self) -> MutexGuard<'_, Registered<K, V>> {
+        crate::lock(&self.registered)
+    }
+
+    /// Copies the hooks selected by `hooks`, so they run without the lock
+    /// held and may register other hooks.
+    fn copy<T: ?Sized>(&self, hooks: fn(&Registered<K, V>) -> &Vec<Arc<T>>) -> Vec<Arc<T>> {
+        hooks(&self.registered()).clone()
+    }
+}
+
+impl<K, V> std::fmt::Debug for Hooks<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let registered = self.registered();
+        f.debug_struct("Hooks")
+            .field("before_insert", &registered.before_insert.len())
+            .field("after_insert", &registered.after_insert.len())
+            .field("before_update", &registered.before_update.len())
+            .field("after_update", &registered.after_update.len())
+            .field("before_delete", &registered.before_delete.len())
+            .field("after_delete", &registered.after_delete.len())
+            .finish()
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Hooks run by the write operations of this FakeDb, to validate, fill
+    /// or cascade the rows they write.
+    pub fn hooks(&self) -> &Hooks<K, V> {
+        &self.hooks
+    }
+
+    /// Fills the timestamps of `value`, then runs the before insert hooks.
+    pub(crate) fn before_insert(&self, operation: Operation, key: &K, value: &mut V) -> Result<()> {
+        if let Some(timestamps) = &self.timestamps {
+            timestamps.inserted(value, self.clock.now());
+        }
+        for hook in self.hooks.copy(|hooks| &hooks.before_insert) {
+            hook(key, value).map_err(|err| self.context(operation).rejected(key, err))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn after_insert(&self, operation: Operation, key: &K, value: &V) -> Result<()> {
+        self.run_row_hooks(operation, |hooks| &hooks.after_insert, key, value)
+    }
+
+    /// Fills the update timestamp of `new`, then runs the before update
+    /// hooks.
+    pub(crate) fn before_update(
+        &self,
+        operation: Operation,
+        key: &K,
+        old: &V,
+        new: &mut V,
+    ) -> Result<()> {
+        if let Some(timestamps) = &self.timestamps {
+            timestamps.updated(new, self.clock.now());
+        }
+        for hook in self.hooks.copy(|hooks| &hooks.before_update) {
+            hook(key, old, new).map_err(|err| self.context(operation).rejected(key, err))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn after_update(
+        &self,
+        operation: Operation,
+        key: &K,
+        old: &V,
+        new: &V,
+    ) -> Result<()> {
+        for hook in self.hooks.copy(|hooks| &hooks.after_update) {
+            hook(key, old, new).map_err(|err| self.context(operation).rejected(key, err))?;
+        }
+        Ok(())
+    }
+
+    /// Runs the before and after delete hooks of the rows of `keys` in
+    /// `storage`.
+    pub(crate) fn delete_hooks(
+        &self,
+        operation: Operation,
+        storage: &crate::Rows<K, V>,
+        keys: &[K],
+    ) -> Result<()> {
+        let rows = || keys.iter().filter_map(|key| Some((key, storage.get(key)?)));
+        for (key, value) in rows() {
+            self.run_row_hooks(operation, |hooks| &hooks.before_delete, key, value)?;
+        }
+        for (key, value) in rows() {
+            self.run_row_hooks(operation, |hooks| &hooks.after_delete, key, value)?;
+        }
+        Ok(())
+    }
+
+    fn run_row_hooks(
+        &self,
+        operation: Operation,
+        hooks: fn(&Registered<K, V>) -> &RowHooks<K, V>,
+        key: &K,
+        value: &V,
+    ) -> Result<()> {
+        for hook in self.hooks.copy(hooks) {
+            hook(key, value).map_err(|err| self.context(operation).rejected(key, err))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{args::UpdateArguments, identifier::Sequence, FakeDbError};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Country {
+        name: &'static str,
+        capital: Option<&'static str>,
+    }
+
+    fn country(name: &'static str) -> Country {
+        Country {
+            name,
+            capital: None,
+        }
+    }
+
+    #[test]
+    fn test_before_insert_hook_fills_values() {
+        let db = FakeDb::new(Sequence::new());
+        db.hooks().before_insert(|_, country: &mut Country| {
+            country.capital.get_or_insert("unknown");
+            Ok(())
+        });
+
+        db.insert(country("Nauru")).unwrap();
+
+        assert_eq!(db.find_by_id(&1).unwrap().unwrap().capital, Some("unknown"));
+    }
+
+    #[test]
+    fn test_rejected_insert_many_stores_nothing() {
+        let db = FakeDb::new(Sequence::new()).with_name("countries");
+        db.hooks()
+            .after_insert(|_, country: &Country| match country.name {
+                "" => Err(Rejection::new("name is empty")),
+                _ => Ok(()),
+            });
+
+        let err = db
+            .insert_many(vec![country("Tuvalu"), country("")])
+            .expect_err("name is empty");
+
+        assert_eq!(
+            err,
+            FakeDbError::Rejected {
+                operation: Operation::InsertMany,
+                table: "countries".into(),
+                key: "2".into(),
+                reason: "name is empty".into(),
+            }
+        );
+        assert!(db.find_by_id(&1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_update_hooks_see_old_and_new_values() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, country("Palau"))]);
+        db.hooks()
+            .before_update(|_, old: &Country, new: &mut Country| {
+                new.capital = old.capital.or(Some("Ngerulmud"));
+                Ok(())
+            });
+        db.hooks().after_update(|_, old: &Country, new: &Country| {
+            if old.name == new.name {
+                Ok(())
+            } else {
+                Err(Rejection::new("name can not change"))
+            }
+        });
+
+        let update = |name: &'static str| {
+            db.update_many(UpdateArguments {
+                matcher: Box::new(|_: &&Country| true),
+                updater: Box::new(move |country: &mut Country| country.name = name),
+            })
+        };
+
+        update("Palau").unwrap();
+        let err = update("Belau").expect_err("name changed");
+
+        assert!(matches!(err, FakeDbError::Rejected { .. }));
+        assert_eq!(
+            db.find_by_id(&1).unwrap().unwrap().capital,
+            Some("Ngerulmud")
+        );
+    }
+
+    #[test]
+    fn test_delete_hooks_cascade_to_other_tables() {
+        let countries = FakeDb::seeded(Sequence::new(), vec![(1, country("Kiribati"))]);
+        let cities = Arc::new(FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, (1, "Tarawa")), (2, (2, "Funafuti"))],
+        ));
+        let cascade = cities.clone();
+        countries.hooks().after_delete(move |id, _| {
+            cascade
+                .delete_many(|(country, _): &&(u32, &str)| country == id)
+                .map(|_| ())
+                .map_err(|err| Rejection::new(err.to_string()))
+        });
+        countries
+            .hooks()
+            .before_delete(|_, country| match country.name {
+                "Kiribati" => Ok(()),
+                _ => Err(Rejection::new("protected")),
+            });
+
+        countries.delete_by_id(&1).unwrap();
+
+        assert!(cities.find_by_id(&1).unwrap().is_none());
+        assert!(cities.find_by_id(&2).unwrap().is_some());
+    }
+}
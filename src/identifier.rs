@@ -1,6 +1,16 @@
-use std::sync::Mutex;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
 
-pub trait Identifier<V> {
+use crate::errors::IdError;
+
+/// Generates the keys of the values stored in a `FakeDb`.
+///
+/// An Identifier keeps no state, so it is `Copy`. Identifiers that keep
+/// state, like `Sequence`, implement `FallibleIdentifier` to be told about
+/// released, observed and restored keys.
+pub trait Identifier<V>: Copy {
     type Id;
     fn new_id(&self, value: &V) -> Self::Id;
 
@@ -9,14 +19,121 @@ pub trait Identifier<V> {
     fn is_autogenerated(&self) -> bool;
 }
 
+/// Generates keys for values that may not have a valid one, like a value
+/// whose id field is `None`, or once a sequence is exhausted.
+///
+/// Every `Identifier` is a `FallibleIdentifier` that never fails. The error
+/// of `try_new_id` is returned by the `FakeDb` operation that asked for the
+/// key.
+///
+/// `try_new_id` takes `&self` and may be called while the storage is locked,
+/// so generators that keep state should rely on atomics rather than locks, as
+/// `AtomicSequence` does.
+pub trait FallibleIdentifier<V> {
+    type Id;
+    /// # Errors
+    /// Returns an IdError when `value` has no valid key, reported by FakeDb as
+    /// an InvalidId error
+    fn try_new_id(&self, value: &V) -> Result<Self::Id, IdError>;
+
+    /// See `Identifier::is_autogenerated`.
+    fn is_autogenerated(&self) -> bool;
+
+    /// Called with the ids generated for values that were not stored because
+    /// the operation failed. Does nothing by default.
+    fn release(&self, _ids: &[Self::Id]) {}
+
+    /// Called with keys that were stored without being generated, like the
+    /// rows a FakeDb is seeded with. Does nothing by default.
+    fn observe(&self, _id: &Self::Id) {}
+
+    /// State saved by `FakeDb::snapshot`, like the last id of a sequence.
+    /// Stateless identifiers return `None`, the default.
+    fn state(&self) -> Option<u64> {
+        None
+    }
+
+    /// Resets the identifier to a state returned by `state`.
+    ///
+    /// # Errors
+    /// Returns an IdError when `state` was not returned by this kind of
+    /// identifier
+    fn set_state(&self, _state: u64) -> Result<(), IdError> {
+        Ok(())
+    }
+}
+
+impl<V, I: Identifier<V>> FallibleIdentifier<V> for I {
+    type Id = I::Id;
+
+    fn try_new_id(&self, value: &V) -> Result<Self::Id, IdError> {
+        Ok(self.new_id(value))
+    }
+
+    fn is_autogenerated(&self) -> bool {
+        Identifier::is_autogenerated(self)
+    }
+}
+
+/// What a sequence does with the ids of a failed insert.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rollback {
+    /// Ids stay used, leaving a gap like a real database sequence.
+    #[default]
+    Gap,
+    /// Ids are handed out again, as long as no other id was generated after
+    /// them.
+    Reclaim,
+}
+
+/// Returns the id a sequence generates after `last_id`.
+fn next(last_id: u32) -> Result<u32, IdError> {
+    last_id.checked_add(1).ok_or_else(|| IdError {
+        reason: format!("sequence exhausted after {last_id}"),
+    })
+}
+
+/// Returns the last id of a sequence saved as `state`.
+fn sequence_state(state: u64) -> Result<u32, IdError> {
+    u32::try_from(state).map_err(|_| IdError {
+        reason: format!("{state} is not the state of a sequence"),
+    })
+}
+
+/// Returns the id a sequence should restart from when `ids` are the last ids
+/// it generated.
+fn reclaimed(last_id: u32, ids: &[u32]) -> Option<u32> {
+    let first = *ids.iter().min()?;
+    let last = *ids.iter().max()?;
+    let contiguous = (last - first) as usize + 1 == ids.len();
+
+    (last == last_id && contiguous).then_some(first - 1)
+}
+
+#[derive(Debug)]
 pub struct Sequence {
     last_id: Mutex<u32>,
+    rollback: Rollback,
 }
 
 impl Sequence {
     pub fn new() -> Self {
+        Self::with_rollback(Rollback::default())
+    }
+
+    pub fn with_rollback(rollback: Rollback) -> Self {
         Self {
             last_id: Mutex::new(0),
+            rollback,
+        }
+    }
+}
+
+impl Clone for Sequence {
+    fn clone(&self) -> Self {
+        Self {
+            last_id: Mutex::new(*crate::lock(&self.last_id)),
+            rollback: self.rollback,
         }
     }
 }
@@ -28,18 +145,123 @@ impl Default for Sequence {
 }
 
 /// Generates sequential
-impl<V> Identifier<V> for Sequence {
+impl<V> FallibleIdentifier<V> for Sequence {
+    type Id = u32;
+
+    fn try_new_id(&self, _: &V) -> Result<Self::Id, IdError> {
+        let mut last_id = crate::lock(&self.last_id);
+        *last_id = next(*last_id)?;
+
+        Ok(*last_id)
+    }
+
+    fn is_autogenerated(&self) -> bool {
+        false
+    }
+
+    fn release(&self, ids: &[u32]) {
+        if self.rollback == Rollback::Reclaim {
+            let mut last_id = crate::lock(&self.last_id);
+            if let Some(id) = reclaimed(*last_id, ids) {
+                *last_id = id;
+            }
+        }
+    }
+
+    fn observe(&self, id: &u32) {
+        let mut last_id = crate::lock(&self.last_id);
+        *last_id = (*last_id).max(*id);
+    }
+
+    fn state(&self) -> Option<u64> {
+        Some(u64::from(*crate::lock(&self.last_id)))
+    }
+
+    fn set_state(&self, state: u64) -> Result<(), IdError> {
+        *crate::lock(&self.last_id) = sequence_state(state)?;
+        Ok(())
+    }
+}
+
+/// Generates sequential ids without taking a lock.
+#[derive(Debug)]
+pub struct AtomicSequence {
+    last_id: AtomicU32,
+    rollback: Rollback,
+}
+
+impl AtomicSequence {
+    pub fn new() -> Self {
+        Self::with_rollback(Rollback::default())
+    }
+
+    pub fn with_rollback(rollback: Rollback) -> Self {
+        Self {
+            last_id: AtomicU32::new(0),
+            rollback,
+        }
+    }
+}
+
+impl Clone for AtomicSequence {
+    fn clone(&self) -> Self {
+        Self {
+            last_id: AtomicU32::new(self.last_id.load(Ordering::Relaxed)),
+            rollback: self.rollback,
+        }
+    }
+}
+
+impl Default for AtomicSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> FallibleIdentifier<V> for AtomicSequence {
     type Id = u32;
-    fn new_id(&self, _: &V) -> Self::Id {
-        let mut last_id = self.last_id.lock().unwrap();
-        *last_id += 1;
 
-        *last_id
+    fn try_new_id(&self, _: &V) -> Result<Self::Id, IdError> {
+        // Fails only with the last id of an exhausted sequence.
+        self.last_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last_id| {
+                last_id.checked_add(1)
+            })
+            .map_or_else(next, |last_id| Ok(last_id + 1))
     }
 
     fn is_autogenerated(&self) -> bool {
         false
     }
+
+    fn release(&self, ids: &[u32]) {
+        if self.rollback == Rollback::Reclaim {
+            let last_id = self.last_id.load(Ordering::Relaxed);
+            if let Some(id) = reclaimed(last_id, ids) {
+                // Losing the race against a new id means the gap stays.
+                let _ = self.last_id.compare_exchange(
+                    last_id,
+                    id,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+
+    fn observe(&self, id: &u32) {
+        self.last_id.fetch_max(*id, Ordering::Relaxed);
+    }
+
+    fn state(&self) -> Option<u64> {
+        Some(u64::from(self.last_id.load(Ordering::Relaxed)))
+    }
+
+    fn set_state(&self, state: u64) -> Result<(), IdError> {
+        self.last_id
+            .store(sequence_state(state)?, Ordering::Relaxed);
+        Ok(())
+    }
 }
 
 /// $Identifier is the identifier name
@@ -55,12 +277,38 @@ impl<V> Identifier<V> for Sequence {
 ///     name: &str
 /// }
 /// impl_identifier!(CountryId<u32, Country>, id);
+///
+/// Suffixing the field with `?` implements a FallibleIdentifier for a field
+/// of type Option<$Id>, that fails when the field is None
+/// impl_identifier!(CountryId<u32, Country>, id?);
 #[macro_export]
 macro_rules! impl_identifier {
+    ($Identifier: ident <$Id:ident, $Value: ident>, $id: ident ?) => {
+        #[derive(Debug, Clone, Copy)]
+        pub struct $Identifier;
+
+        impl $crate::identifier::FallibleIdentifier<$Value> for $Identifier {
+            type Id = $Id;
+
+            fn try_new_id(
+                &self,
+                value: &$Value,
+            ) -> ::std::result::Result<Self::Id, $crate::errors::IdError> {
+                value.$id.ok_or_else(|| $crate::errors::IdError {
+                    reason: format!("{} is None", stringify!($id)),
+                })
+            }
+
+            fn is_autogenerated(&self) -> bool {
+                true
+            }
+        }
+    };
     ($Identifier: ident <$Id:ident, $Value: ident>, $id: ident) => {
+        #[derive(Debug, Clone, Copy)]
         pub struct $Identifier;
 
-        impl Identifier<$Value> for $Identifier {
+        impl $crate::identifier::Identifier<$Value> for $Identifier {
             type Id = $Id;
 
             fn new_id(&self, value: &$Value) -> Self::Id {
@@ -79,13 +327,107 @@ mod test {
 
     use super::*;
 
+    fn next_id(identifier: &impl FallibleIdentifier<(), Id = u32>) -> u32 {
+        identifier.try_new_id(&()).unwrap()
+    }
+
     #[test]
     fn test_sequence() {
         let sequence = Sequence::new();
 
-        assert_eq!(sequence.new_id(&()), 1);
-        assert_eq!(sequence.new_id(&()), 2);
-        assert_eq!(sequence.new_id(&()), 3);
-        assert!(!Identifier::<()>::is_autogenerated(&sequence));
+        assert_eq!(next_id(&sequence), 1);
+        assert_eq!(next_id(&sequence), 2);
+        assert_eq!(next_id(&sequence), 3);
+        assert!(!FallibleIdentifier::<()>::is_autogenerated(&sequence));
+    }
+
+    #[test]
+    fn test_atomic_sequence() {
+        let sequence = AtomicSequence::new();
+
+        assert_eq!(next_id(&sequence), 1);
+        assert_eq!(next_id(&sequence), 2);
+        assert_eq!(next_id(&sequence), 3);
+        assert!(!FallibleIdentifier::<()>::is_autogenerated(&sequence));
+    }
+
+    #[test]
+    fn test_sequence_keeps_gaps_by_default() {
+        let sequence = Sequence::new();
+
+        let ids = [next_id(&sequence), next_id(&sequence)];
+        FallibleIdentifier::<()>::release(&sequence, &ids);
+
+        assert_eq!(next_id(&sequence), 3);
+    }
+
+    #[test]
+    fn test_sequence_reclaims_released_ids() {
+        let sequence = Sequence::with_rollback(Rollback::Reclaim);
+        let atomic = AtomicSequence::with_rollback(Rollback::Reclaim);
+
+        next_id(&sequence);
+        let ids = [next_id(&sequence), next_id(&sequence)];
+        FallibleIdentifier::<()>::release(&sequence, &ids);
+        next_id(&atomic);
+        let ids = [next_id(&atomic), next_id(&atomic)];
+        FallibleIdentifier::<()>::release(&atomic, &ids);
+
+        assert_eq!(next_id(&sequence), 2);
+        assert_eq!(next_id(&atomic), 2);
+    }
+
+    #[test]
+    fn test_sequence_keeps_gap_when_released_ids_are_not_the_last() {
+        let sequence = Sequence::with_rollback(Rollback::Reclaim);
+
+        let id = next_id(&sequence);
+        next_id(&sequence);
+        FallibleIdentifier::<()>::release(&sequence, &[id]);
+
+        assert_eq!(next_id(&sequence), 3);
+    }
+
+    #[test]
+    fn test_sequence_moves_past_observed_ids() {
+        let sequence = Sequence::new();
+        let atomic = AtomicSequence::new();
+
+        FallibleIdentifier::<()>::observe(&sequence, &7);
+        FallibleIdentifier::<()>::observe(&sequence, &3);
+        FallibleIdentifier::<()>::observe(&atomic, &7);
+
+        assert_eq!(next_id(&sequence), 8);
+        assert_eq!(next_id(&atomic), 8);
+    }
+
+    #[test]
+    fn test_sequence_rejects_states_beyond_u32() {
+        let sequence = Sequence::new();
+        let atomic = AtomicSequence::new();
+        let state = u64::from(u32::MAX) + 1;
+
+        assert!(FallibleIdentifier::<()>::set_state(&sequence, state).is_err());
+        assert!(FallibleIdentifier::<()>::set_state(&atomic, state).is_err());
+        assert_eq!(next_id(&sequence), 1);
+        assert_eq!(next_id(&atomic), 1);
+    }
+
+    #[test]
+    fn test_atomic_sequence_is_unique_across_threads() {
+        let sequence = AtomicSequence::new();
+
+        let mut ids: Vec<u32> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| (0..100).map(|_| next_id(&sequence)).collect::<Vec<_>>()))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        ids.sort_unstable();
+
+        assert_eq!(ids, (1..=400).collect::<Vec<_>>());
     }
 }
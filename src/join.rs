@@ -0,0 +1,288 @@
+//! Joins between the rows of two FakeDbs.
+use core::hash::Hash;
+use std::collections::HashMap;
+
+use crate::{args::FindArguments, identifier::FallibleIdentifier, FakeDb, Operation, Result, Rows};
+
+/// Rows of two FakeDbs matched on a join key, returned by `FakeDb::join`.
+///
+/// Each FakeDb is read with the latency and faults of `find_many`, without
+/// a journal record, one after the other so no lock is held on both. Rows
+/// whose key extractor returns `None` match nothing, like `NULL` in SQL.
+/// Soft deleted rows are left out.
+pub struct Join<'db, LK, LV, LI, RK, RV, RI, LF, RF>
+where
+    LK: Eq + Hash + std::fmt::Debug + Clone,
+    LV: Clone,
+    LI: FallibleIdentifier<LV, Id = LK>,
+    RK: Eq + Hash + std::fmt::Debug + Clone,
+    RV: Clone,
+    RI: FallibleIdentifier<RV, Id = RK>,
+{
+    left: &'db FakeDb<LK, LV, LI>,
+    right: &'db FakeDb<RK, RV, RI>,
+    left_key: LF,
+    right_key: RF,
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Joins the rows of this FakeDb with the rows of `right` whose keys,
+    /// as extracted from each key and value, are equal:
+    ///
+    /// ```ignore
+    /// cities
+    ///     .join(&countries, |_, city| city.country, |id, _| Some(*id))
+    ///     .inner(FindArguments::default())?
+    /// ```
+    pub fn join<'db, RK, RV, RI, J, LF, RF>(
+        &'db self,
+        right: &'db FakeDb<RK, RV, RI>,
+        left_key: LF,
+        right_key: RF,
+    ) -> Join<'db, K, V, I, RK, RV, RI, LF, RF>
+    where
+        RK: Eq + Hash + std::fmt::Debug + Clone,
+        RV: Clone,
+        RI: FallibleIdentifier<RV, Id = RK>,
+        J: Eq + Hash,
+        LF: Fn(&K, &V) -> Option<J>,
+        RF: Fn(&RK, &RV) -> Option<J>,
+    {
+        Join {
+            left: self,
+            right,
+            left_key,
+            right_key,
+        }
+    }
+
+    /// Copies the rows for a join, which is O(1).
+    fn join_rows(&self) -> Result<Rows<K, V>> {
+        self.latency.block(Operation::FindMany);
+        let rows = self.lock(Operation::FindMany)?.clone();
+        Ok(rows)
+    }
+}
+
+impl<LK, LV, LI, RK, RV, RI, J, LF, RF> Join<'_, LK, LV, LI, RK, RV, RI, LF, RF>
+where
+    LK: Eq + Hash + std::fmt::Debug + Clone,
+    LV: Clone,
+    LI: FallibleIdentifier<LV, Id = LK>,
+    RK: Eq + Hash + std::fmt::Debug + Clone,
+    RV: Clone,
+    RI: FallibleIdentifier<RV, Id = RK>,
+    J: Eq + Hash,
+    LF: Fn(&LK, &LV) -> Option<J>,
+    RF: Fn(&RK, &RV) -> Option<J>,
+{
+    /// Pairs of left and right rows with equal keys.
+    ///
+    /// # Errors
+    /// Locking either FakeDb may result in a error
+    pub fn inner(self, args: FindArguments<(LV, RV)>) -> Result<Vec<(LV, RV)>> {
+        let rows = self
+            .matches()?
+            .into_iter()
+            .flat_map(|(left, rights)| {
+                rights
+                    .into_iter()
+                    .map(move |right| (left.clone(), right.clone()))
+            })
+            .collect();
+        Ok(select(rows, args))
+    }
+
+    /// Pairs of left and right rows with equal keys, and left rows without a
+    /// match paired with `None`.
+    ///
+    /// # Errors
+    /// Locking either FakeDb may result in a error
+    pub fn left(self, args: FindArguments<(LV, Option<RV>)>) -> Result<Vec<(LV, Option<RV>)>> {
+        let mut rows = Vec::new();
+        for (left, rights) in self.matches()? {
+            if rights.is_empty() {
+                rows.push((left, None));
+            } else {
+                rows.extend(
+                    rights
+                        .into_iter()
+                        .map(|right| (left.clone(), Some(right.clone()))),
+                );
+            }
+        }
+        Ok(select(rows, args))
+    }
+
+    /// Left rows with at least one match, once each.
+    ///
+    /// # Errors
+    /// Locking either FakeDb may result in a error
+    pub fn semi(self, args: FindArguments<LV>) -> Result<Vec<LV>> {
+        self.filter(true, args)
+    }
+
+    /// Left rows without any match.
+    ///
+    /// # Errors
+    /// Locking either FakeDb may result in a error
+    pub fn anti(self, args: FindArguments<LV>) -> Result<Vec<LV>> {
+        self.filter(false, args)
+    }
+
+    fn filter(self, matched: bool, args: FindArguments<LV>) -> Result<Vec<LV>> {
+        let rows = self
+            .matches()?
+            .into_iter()
+            .filter(|(_, rights)| rights.is_empty() != matched)
+            .map(|(left, _)| left)
+            .collect();
+        Ok(select(rows, args))
+    }
+
+    /// Every left row with the right rows it matches.
+    fn matches(&self) -> Result<Vec<(LV, Vec<RV>)>> {
+        let left = self.left.join_rows()?;
+        let right = self.right.join_rows()?;
+
+        let mut index = HashMap::<J, Vec<&RV>>::new();
+        for (key, value) in &right {
+            if self.right.is_deleted(value) {
+                continue;
+            }
+            if let Some(join_key) = (self.right_key)(key, value) {
+                index.entry(join_key).or_default().push(value);
+            }
+        }
+
+        Ok(left
+            .iter()
+            .filter(|(_, value)| !self.left.is_deleted(value))
+            .map(|(key, value)| {
+                let rights = (self.left_key)(key, value)
+                    .and_then(|join_key| index.get(&join_key))
+                    .map(|rights| rights.iter().map(|right| (*right).clone()).collect())
+                    .unwrap_or_default();
+                (value.clone(), rights)
+            })
+            .collect())
+    }
+}
+
+/// Keeps the rows `matcher` accepts, sorted by `order` if set.
+fn select<T>(rows: Vec<T>, FindArguments { mut matcher, order }: FindArguments<T>) -> Vec<T> {
+    let mut rows: Vec<T> = rows.into_iter().filter(|row| matcher(&row)).collect();
+    if let Some(mut order) = order {
+        rows.sort_by(|a, b| order(a, b));
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        identifier::Sequence,
+        test_support::{all, all_by},
+    };
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct City {
+        name: &'static str,
+        country: Option<u32>,
+    }
+
+    fn city(name: &'static str, country: Option<u32>) -> City {
+        City { name, country }
+    }
+
+    #[test]
+    fn test_inner_join_pairs_matching_rows() {
+        let cities = FakeDb::seeded(
+            Sequence::new(),
+            vec![
+                (1, city("Lima", Some(1))),
+                (2, city("Cusco", Some(1))),
+                (3, city("Santiago", Some(2))),
+                (4, city("Atlantis", None)),
+            ],
+        );
+        let countries = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, "Peru"), (2, "Chile"), (3, "Bolivia")],
+        );
+
+        let rows = cities
+            .join(&countries, |_, city| city.country, |id, _| Some(*id))
+            .inner(FindArguments {
+                matcher: Box::new(|(_, country)| *country == "Peru"),
+                ..all_by(|(city, _)| city.name)
+            })
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                (city("Cusco", Some(1)), "Peru"),
+                (city("Lima", Some(1)), "Peru")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_left_join_keeps_unmatched_rows() {
+        let cities = FakeDb::seeded(
+            Sequence::new(),
+            vec![
+                (1, city("Lima", Some(1))),
+                (2, city("Cusco", Some(1))),
+                (3, city("Santiago", Some(2))),
+                (4, city("Atlantis", None)),
+            ],
+        );
+        let countries = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, "Peru"), (2, "Chile"), (3, "Bolivia")],
+        );
+
+        let rows = cities
+            .join(&countries, |_, city| city.country, |id, _| Some(*id))
+            .left(all_by(|(city, _)| city.name))
+            .unwrap();
+
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0], (city("Atlantis", None), None));
+        assert_eq!(rows[3], (city("Santiago", Some(2)), Some("Chile")));
+    }
+
+    #[test]
+    fn test_semi_and_anti_joins_filter_left_rows() {
+        let cities = FakeDb::seeded(
+            Sequence::new(),
+            vec![
+                (1, city("Lima", Some(1))),
+                (2, city("Cusco", Some(1))),
+                (3, city("Santiago", Some(2))),
+                (4, city("Atlantis", None)),
+            ],
+        );
+        let countries = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, "Peru"), (2, "Chile"), (3, "Bolivia")],
+        );
+        let join = || countries.join(&cities, |id, _| Some(*id), |_, city| city.country);
+
+        assert_eq!(
+            join().semi(all_by(|country| country)).unwrap(),
+            vec!["Chile", "Peru"]
+        );
+        assert_eq!(join().anti(all()).unwrap(), vec!["Bolivia"]);
+    }
+}
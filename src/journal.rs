@@ -0,0 +1,143 @@
+use core::hash::Hash;
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use crate::{identifier::FallibleIdentifier, FakeDb, FakeDbError, Operation};
+
+/// An operation executed by a FakeDb.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record<K> {
+    pub operation: Operation,
+    /// Keys of the rows the operation returned or changed.
+    pub keys: Vec<K>,
+    /// Short description of the arguments, like `2 values`.
+    pub arguments: String,
+    pub outcome: Result<(), FakeDbError>,
+    pub at: SystemTime,
+}
+
+impl<K> Record<K> {
+    /// Returns `true` if the operation succeeded and changed the storage.
+    pub fn is_write(&self) -> bool {
+        self.operation.is_write() && self.outcome.is_ok() && !self.keys.is_empty()
+    }
+}
+
+/// Records of the operations executed by a FakeDb, kept only when the FakeDb
+/// is created `with_journal`.
+#[derive(Debug)]
+pub(crate) struct Journal<K> {
+    enabled: bool,
+    records: Mutex<Vec<Record<K>>>,
+}
+
+impl<K> Default for Journal<K> {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            records: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<K> Journal<K> {
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn push(&self, record: Record<K>) {
+        self.records().push(record);
+    }
+
+    fn records(&self) -> MutexGuard<'_, Vec<Record<K>>> {
+        crate::lock(&self.records)
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Records every operation of this FakeDb, to be inspected with
+    /// `operations` and the `assert_*` helpers.
+    pub fn with_journal(mut self) -> Self {
+        self.journal = Journal::enabled();
+        self
+    }
+
+    /// Operations executed since the FakeDb was created or the journal was
+    /// cleared, oldest first.
+    pub fn operations(&self) -> Vec<Record<K>> {
+        self.journal.records().clone()
+    }
+
+    pub fn clear_operations(&self) {
+        self.journal.records().clear();
+    }
+
+    /// # Panics
+    /// Panics if no insert stored `key`
+    pub fn assert_inserted(&self, key: &K) {
+        self.assert_written(key, Operation::is_insert, "inserted");
+    }
+
+    /// # Panics
+    /// Panics if no update changed `key`
+    pub fn assert_updated(&self, key: &K) {
+        self.assert_written(key, Operation::is_update, "updated");
+    }
+
+    /// # Panics
+    /// Panics if no delete removed `key`
+    pub fn assert_deleted(&self, key: &K) {
+        self.assert_written(key, Operation::is_delete, "deleted");
+    }
+
+    /// # Panics
+    /// Panics if any operation changed the storage
+    pub fn assert_no_writes(&self) {
+        let writes: Vec<_> = self
+            .checked_operations()
+            .into_iter()
+            .filter(Record::is_write)
+            .collect();
+        assert!(
+            writes.is_empty(),
+            "expected no writes on {}, found {writes:#?}",
+            self.name()
+        );
+    }
+
+    fn assert_written(&self, key: &K, kind: fn(&Operation) -> bool, verb: &str) {
+        let operations = self.checked_operations();
+        let written = operations
+            .iter()
+            .filter(|record| record.is_write() && kind(&record.operation))
+            .any(|record| record.keys.contains(key));
+        assert!(
+            written,
+            "expected {key:?} to be {verb} on {}, operations were {operations:#?}",
+            self.name()
+        );
+    }
+
+    fn checked_operations(&self) -> Vec<Record<K>> {
+        assert!(
+            self.journal.is_enabled(),
+            "operations of {} are not recorded, create it with_journal",
+            self.name()
+        );
+        self.operations()
+    }
+}
@@ -0,0 +1,276 @@
+//! JSON dump and load of the rows of a FakeDb, with the `serde` feature.
+//!
+//! A dump is an object holding the identifier state and the rows as a list
+//! of key and value pairs, so keys do not need to be strings:
+//!
+//! ```json
+//! {
+//!   "identifier": 2,
+//!   "rows": [
+//!     { "key": 1, "value": "Bhutan" },
+//!     { "key": 2, "value": "Nepal" }
+//!   ]
+//! }
+//! ```
+use core::hash::Hash;
+use std::io;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    default_name, errors::Context, identifier::FallibleIdentifier, FakeDb, FakeDbError, Operation,
+    Result, Rows,
+};
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Dump<K, V> {
+    #[serde(default)]
+    pub identifier: Option<u64>,
+    pub rows: Vec<Row<K, V>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Row<K, V> {
+    pub key: K,
+    pub value: V,
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Serializes the rows and identifier state of this FakeDb as pretty
+    /// printed JSON. Rows are in no particular order.
+    ///
+    /// # Errors
+    ///  * A key or value that fails to serialize results in a Format error
+    ///  * Locking may result in a error
+    pub fn to_json(&self) -> Result<String>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let mut json = Vec::new();
+        self.write_json(&mut json)?;
+
+        Ok(String::from_utf8(json).expect("serde_json writes UTF-8"))
+    }
+
+    /// Writes the JSON returned by `to_json` to `writer`.
+    ///
+    /// # Errors
+    ///  * A key or value that fails to serialize results in a Format error
+    ///  * A failed write results in an Io error
+    ///  * Locking may result in a error
+    pub fn write_json<W: io::Write>(&self, writer: W) -> Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        // Rows are cloned in O(1) so the lock is not held while writing.
+        let rows = self.lock_storage(Operation::Dump)?.clone();
+        let dump = Dump {
+            identifier: self.identifier.state(),
+            rows: rows.iter().map(|(key, value)| Row { key, value }).collect(),
+        };
+
+        serde_json::to_writer_pretty(writer, &dump)
+            .map_err(|err| json_error(&self.context(Operation::Dump), err))
+    }
+
+    /// Creates a FakeDb from JSON written by `to_json`.
+    ///
+    /// Keys the identifier did not generate are observed as with `seeded`,
+    /// then the identifier is reset to the dumped state, if any.
+    ///
+    /// # Errors
+    ///  * Invalid JSON results in a Format error
+    ///  * Rows with the same key result in a Cardinality error
+    pub fn from_json(identifier: I, json: &str) -> Result<Self>
+    where
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+    {
+        Self::read_json(identifier, json.as_bytes())
+    }
+
+    /// Creates a FakeDb from the JSON read from `reader`, see `from_json`.
+    ///
+    /// # Errors
+    ///  * Invalid JSON results in a Format error
+    ///  * A failed read results in an Io error
+    ///  * Rows with the same key result in a Cardinality error
+    pub fn read_json<R: io::Read>(identifier: I, reader: R) -> Result<Self>
+    where
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+    {
+        let table = default_name::<V>();
+        let context = Context {
+            operation: Operation::Load,
+            table: &table,
+        };
+        let dump: Dump<K, V> =
+            serde_json::from_reader(reader).map_err(|err| json_error(&context, err))?;
+
+        let mut rows = Rows::new();
+        for Row { key, value } in dump.rows {
+            if rows.contains_key(&key) {
+                return Err(context.cardinality(&key));
+            }
+            rows.insert(key, value);
+        }
+        // Seeding observes the keys after the state is set, so a state
+        // behind the stored keys does not hand them out again.
+        if let Some(state) = dump.identifier {
+            identifier
+                .set_state(state)
+                .map_err(|err| context.format(err.reason))?;
+        }
+
+        Ok(Self::seeded(identifier, rows))
+    }
+}
+
+/// Reports the IO failures of serde_json as Io errors, and invalid JSON as
+/// Format errors.
+fn json_error(context: &Context, err: serde_json::Error) -> FakeDbError {
+    if err.is_io() {
+        context.io(err)
+    } else {
+        context.format(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{identifier::Sequence, FakeDbError};
+
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip_keeps_rows_and_sequence() {
+        let db = FakeDb::new(Sequence::new());
+        db.insert_many(vec!["Bhutan".to_owned(), "Nepal".to_owned()])
+            .unwrap();
+        db.delete_by_id(&2).unwrap();
+
+        let loaded =
+            FakeDb::<u32, String, _>::from_json(Sequence::new(), &db.to_json().unwrap()).unwrap();
+        let id = loaded.insert("Sikkim".to_owned()).unwrap();
+
+        assert_eq!(loaded.find_by_id(&1).unwrap().as_deref(), Some("Bhutan"));
+        assert_eq!(loaded.find_by_id(&2).unwrap(), None);
+        assert_eq!(id, 3);
+    }
+
+    #[test]
+    fn test_from_json_reads_fixture_without_identifier_state() {
+        let json = r#"{ "rows": [{ "key": 7, "value": "Laos" }] }"#;
+
+        let db = FakeDb::<u32, String, _>::from_json(Sequence::new(), json).unwrap();
+        let id = db.insert("Myanmar".to_owned()).unwrap();
+
+        assert_eq!(db.find_by_id(&7).unwrap().as_deref(), Some("Laos"));
+        assert_eq!(id, 8);
+    }
+
+    #[test]
+    fn test_from_json_moves_identifier_state_past_stored_keys() {
+        let json = r#"{ "identifier": 6, "rows": [{ "key": 7, "value": "Laos" }] }"#;
+
+        let db = FakeDb::<u32, String, _>::from_json(Sequence::new(), json).unwrap();
+
+        assert_eq!(db.insert("Myanmar".to_owned()).unwrap(), 8);
+    }
+
+    #[test]
+    fn test_from_json_rejects_invalid_json() {
+        let err = FakeDb::<u32, String, _>::from_json(Sequence::new(), r#"{ "rows": [1] }"#)
+            .expect_err("rows are not key and value pairs");
+
+        assert!(matches!(
+            err,
+            FakeDbError::Format {
+                operation: Operation::Load,
+                ..
+            }
+        ));
+    }
+
+    /// Fails every read and write.
+    struct Unplugged;
+
+    impl io::Read for Unplugged {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("unplugged"))
+        }
+    }
+
+    impl io::Write for Unplugged {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("unplugged"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_reports_failed_reads_and_writes_as_io() {
+        let db = FakeDb::new(Sequence::new());
+        db.insert("Bhutan".to_owned()).unwrap();
+
+        let write = db.write_json(Unplugged).expect_err("the writer fails");
+        let read = FakeDb::<u32, String, _>::read_json(Sequence::new(), Unplugged)
+            .expect_err("the reader fails");
+
+        assert!(matches!(
+            write,
+            FakeDbError::Io {
+                operation: Operation::Dump,
+                ..
+            }
+        ));
+        assert!(matches!(
+            read,
+            FakeDbError::Io {
+                operation: Operation::Load,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_from_json_rejects_identifier_state_of_another_identifier() {
+        let json = r#"{ "identifier": 4294967296, "rows": [] }"#;
+
+        let err = FakeDb::<u32, String, _>::from_json(Sequence::new(), json)
+            .expect_err("a sequence state fits in a u32");
+
+        assert_eq!(
+            err,
+            FakeDbError::Format {
+                operation: Operation::Load,
+                table: "String".into(),
+                message: "4294967296 is not the state of a sequence".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_json_rejects_repeated_keys() {
+        let json = r#"{ "rows": [{ "key": 7, "value": "Laos" }, { "key": 7, "value": "Laos" }] }"#;
+
+        let err = FakeDb::<u32, String, _>::from_json(Sequence::new(), json)
+            .expect_err("key 7 is repeated");
+
+        assert_eq!(
+            err.to_string(),
+            "load on String: key 7 is repeated".to_owned()
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::operation::Operation;
+
+/// How long an operation waits before it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Latency {
+    Fixed(Duration),
+    /// A random duration between both bounds, inclusive.
+    Between(Duration, Duration),
+}
+
+/// Waits for the latency of blocking FakeDb operations.
+pub trait Sleeper: Send + Sync {
+    fn sleep(&self, duration: Duration);
+}
+
+/// Sleeps the current thread.
+pub struct ThreadSleeper;
+
+impl Sleeper for ThreadSleeper {
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+struct State {
+    all: Option<Latency>,
+    operations: HashMap<Operation, Latency>,
+    seed: u64,
+}
+
+/// Adds artificial latency to FakeDb operations, so timeouts and circuit
+/// breakers can be tested.
+///
+/// Blocking operations wait through the `Sleeper`, a `ThreadSleeper` by
+/// default. With the `async` feature, the operations of `FakeDb::nonblocking`
+/// wait with `tokio::time::sleep` instead.
+pub struct LatencyInjector {
+    state: Mutex<State>,
+    sleeper: Mutex<Arc<dyn Sleeper>>,
+}
+
+impl Default for LatencyInjector {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                all: None,
+                operations: HashMap::new(),
+                seed: 0x2545_f491_4f6c_dd1d,
+            }),
+            sleeper: Mutex::new(Arc::new(ThreadSleeper)),
+        }
+    }
+}
+
+impl LatencyInjector {
+    /// Sets the latency of every operation without a latency of its own.
+    pub fn set(&self, latency: Latency) {
+        self.state().all = Some(latency);
+    }
+
+    /// Sets the latency of `operation`.
+    pub fn set_for(&self, operation: Operation, latency: Latency) {
+        self.state().operations.insert(operation, latency);
+    }
+
+    /// Removes every latency.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.all = None;
+        state.operations.clear();
+    }
+
+    /// Seeds the random durations of `Latency::Between`.
+    pub fn seed(&self, seed: u64) {
+        // xorshift never leaves zero.
+        self.state().seed = seed.max(1);
+    }
+
+    pub fn set_sleeper<S: Sleeper + 'static>(&self, sleeper: S) {
+        *crate::lock(&self.sleeper) = Arc::new(sleeper);
+    }
+
+    /// Returns how long `operation` must wait, if at all.
+    pub fn delay(&self, operation: Operation) -> Option<Duration> {
+        let mut state = self.state();
+        let latency = state.operations.get(&operation).or(state.all.as_ref())?;
+        match *latency {
+            Latency::Fixed(duration) => Some(duration),
+            Latency::Between(min, max) => {
+                let span = max.saturating_sub(min).as_nanos() as u64;
+                let random = state.next_random();
+                Some(min + Duration::from_nanos(random % span.saturating_add(1)))
+            }
+        }
+    }
+
+    /// Waits for the latency of `operation` through the sleeper, without
+    /// holding its lock, so concurrent operations wait at the same time.
+    pub(crate) fn block(&self, operation: Operation) {
+        if let Some(duration) = self.delay(operation) {
+            let sleeper = crate::lock(&self.sleeper).clone();
+            sleeper.sleep(duration);
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        crate::lock(&self.state)
+    }
+}
+
+impl State {
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.seed;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.seed = x;
+        x
+    }
+}
+
+impl fmt::Debug for LatencyInjector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("LatencyInjector")
+            .field("all", &state.all)
+            .field("operations", &state.operations)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operation_latency_overrides_latency_of_all() {
+        let latency = LatencyInjector::default();
+        latency.set(Latency::Fixed(Duration::from_millis(10)));
+        latency.set_for(Operation::Insert, Latency::Fixed(Duration::from_millis(50)));
+
+        assert_eq!(
+            latency.delay(Operation::Insert),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            latency.delay(Operation::FindOne),
+            Some(Duration::from_millis(10))
+        );
+
+        latency.clear();
+        assert_eq!(latency.delay(Operation::Insert), None);
+    }
+
+    #[test]
+    fn test_random_latency_stays_within_bounds() {
+        let latency = LatencyInjector::default();
+        let (min, max) = (Duration::from_millis(5), Duration::from_millis(8));
+        latency.set(Latency::Between(min, max));
+
+        for _ in 0..100 {
+            let delay = latency.delay(Operation::FindMany).unwrap();
+            assert!(min <= delay && delay <= max);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_operations_wait_at_the_same_time() {
+        let latency = LatencyInjector::default();
+        latency.set(Latency::Fixed(Duration::from_millis(200)));
+
+        let start = std::time::Instant::now();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| latency.block(Operation::FindOne));
+            }
+        });
+
+        assert!(start.elapsed() < Duration::from_millis(600));
+    }
+}
@@ -2,28 +2,108 @@
 use core::hash::Hash;
 use std::{
     collections::{HashMap, HashSet},
-    sync::MutexGuard,
+    sync::{Arc, MutexGuard, OnceLock},
+    time::Duration,
 };
 
 use args::{FindArguments, Matcher, UpdateArguments, Updater};
-use errors::{locking, Cardinality, Conflict, KeyNotFound};
-use http_problem::Result;
-use identifier::{Identifier, Sequence};
+use clock::{Clock, SystemClock, Timestamps};
+use errors::Context;
+pub use errors::{FakeDbError, Result};
+use expiry::Expiry;
+use fault::FaultInjector;
+use foreign_key::{RelationGuard, Relations};
+use history::History;
+use hook::Hooks;
+use identifier::{FallibleIdentifier, Sequence};
+use journal::{Journal, Record};
+use latency::LatencyInjector;
+use observer::{Batch, Event, Observers};
+pub use operation::Operation;
+use soft_delete::SoftDelete;
+use wal::{Change, Log};
 pub mod args;
+pub mod clock;
+pub mod diff;
 pub mod errors;
+mod expiry;
+pub mod fault;
+#[cfg(any(feature = "yaml", feature = "toml", feature = "csv"))]
+pub mod fixture;
+pub mod foreign_key;
+pub mod history;
+pub mod hook;
 pub mod identifier;
+pub mod join;
+pub mod journal;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod latency;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+pub mod observer;
+pub mod operation;
+pub mod snapshot;
+mod soft_delete;
+pub mod table;
+#[cfg(test)]
+mod test_support;
+mod wal;
+#[cfg(feature = "wal")]
+pub use wal::Wal;
 
 use std::sync::Mutex;
 
-#[derive(Debug)]
+/// Rows of a FakeDb. Cloning them is O(1), as clones share unchanged rows.
+pub type Rows<K, V> = im::HashMap<K, V>;
+
+/// Debug prints the rows as a `table::Table`, as they are stored: expired
+/// rows are not reclaimed, and a FakeDb locked by an operation, like one
+/// printed from its own hook, prints as `FakeDb { .. }`.
 pub struct FakeDb<K, V, I>
 where
     K: Eq + Hash + std::fmt::Debug + Clone,
     V: Clone,
-    I: Identifier<V, Id = K>,
+    I: FallibleIdentifier<V, Id = K>,
 {
-    storage: Mutex<HashMap<K, V>>,
+    storage: Mutex<Rows<K, V>>,
     identifier: I,
+    name: String,
+    collision_retries: usize,
+    faults: FaultInjector,
+    latency: LatencyInjector,
+    journal: Journal<K>,
+    log: Option<Box<dyn Log<K, V>>>,
+    observers: Observers<K, V>,
+    hooks: Hooks<K, V>,
+    clock: Arc<dyn Clock>,
+    timestamps: Option<Timestamps<V>>,
+    expiry: Expiry<K, V>,
+    soft_delete: Option<SoftDelete<V>>,
+    history: History<K, V>,
+    /// Set once the FakeDb takes part in a foreign key.
+    relations: OnceLock<Arc<Relations>>,
+}
+
+/// Locked storage of a FakeDb, which also holds the lock of the FakeDbs
+/// related by foreign keys until the storage is unlocked.
+pub(crate) struct Locked<'a, K, V> {
+    storage: MutexGuard<'a, Rows<K, V>>,
+    _relations: Option<RelationGuard>,
+}
+
+impl<K, V> std::ops::Deref for Locked<'_, K, V> {
+    type Target = Rows<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.storage
+    }
+}
+
+impl<K, V> std::ops::DerefMut for Locked<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.storage
+    }
 }
 
 impl<V> Default for FakeDb<u32, V, Sequence>
@@ -39,42 +119,169 @@ impl<K, V, I> FakeDb<K, V, I>
 where
     K: Eq + Hash + std::fmt::Debug + Clone,
     V: Clone,
-    I: Identifier<V, Id = K>,
+    I: FallibleIdentifier<V, Id = K>,
 {
     pub fn new(identifier: I) -> Self {
         Self {
-            storage: Mutex::new(HashMap::new()),
+            storage: Mutex::new(Rows::new()),
             identifier,
+            name: default_name::<V>(),
+            collision_retries: 0,
+            faults: FaultInjector::default(),
+            latency: LatencyInjector::default(),
+            journal: Journal::default(),
+            log: None,
+            observers: Observers::default(),
+            hooks: Hooks::default(),
+            clock: Arc::new(SystemClock),
+            timestamps: None,
+            expiry: Expiry::default(),
+            soft_delete: None,
+            history: History::default(),
+            relations: OnceLock::new(),
         }
     }
 
+    /// Creates a FakeDb that already stores `rows`.
+    ///
+    /// The identifier observes every seeded key, so a `Sequence` continues
+    /// after the highest of them.
+    pub fn seeded<R: IntoIterator<Item = (K, V)>>(identifier: I, rows: R) -> Self {
+        let storage: Rows<K, V> = rows.into_iter().collect();
+        for id in storage.keys() {
+            identifier.observe(id);
+        }
+
+        Self {
+            storage: Mutex::new(storage),
+            ..Self::new(identifier)
+        }
+    }
+
+    /// Names the table in the errors of this FakeDb. Defaults to the name of
+    /// the stored type.
+    pub fn with_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Rules that make the operations of this FakeDb fail, to simulate an
+    /// unavailable database.
+    pub fn faults(&self) -> &FaultInjector {
+        &self.faults
+    }
+
+    /// Artificial latency added to the operations of this FakeDb.
+    pub fn latency(&self) -> &LatencyInjector {
+        &self.latency
+    }
+
+    /// Creates an independent FakeDb starting from the rows and identifier
+    /// state of this one.
+    ///
+    /// The fork shares unchanged rows with this FakeDb, so forking is O(1)
+    /// whatever the number of rows. It reads the same clock, expires rows at
+    /// the same times and soft deletes them the same way, but faults,
+    /// latency, journal, subscribers, hooks, timestamps and history are not
+    /// copied.
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn fork(&self) -> Result<Self>
+    where
+        I: Clone,
+    {
+        let storage = self.lock_storage(Operation::Fork)?;
+        // Copied under the lock, so the deadlines match the rows.
+        let expiry = self.expiry.fork();
+        Ok(Self {
+            storage: Mutex::new(storage.clone()),
+            name: self.name.clone(),
+            collision_retries: self.collision_retries,
+            clock: self.clock.clone(),
+            expiry,
+            soft_delete: self.soft_delete.clone(),
+            ..Self::new(self.identifier.clone())
+        })
+    }
+
+    /// Generates up to `retries` new ids when an inserted value collides with
+    /// a key in storage, instead of failing with a Conflict error.
+    ///
+    /// Only applies to identifiers that do not derive ids from the value.
+    pub fn with_collision_retries(mut self, retries: usize) -> Self {
+        self.collision_retries = retries;
+        self
+    }
+
     /// # Errors
     /// Locking may result in a error
     pub fn find_by_id(&self, id: &K) -> Result<Option<V>> {
-        let storage = self.storage.lock().map_err(locking)?;
-        Ok(storage.get(id).cloned())
+        self.latency.block(Operation::FindById);
+        self.do_find_by_id(id)
+    }
+
+    pub(crate) fn do_find_by_id(&self, id: &K) -> Result<Option<V>> {
+        let result = self.lock(Operation::FindById).map(|storage| {
+            let value = storage
+                .get(id)
+                .filter(|value| !self.is_deleted(value))
+                .cloned();
+            let keys = value.iter().map(|_| id.clone()).collect();
+            (keys, value)
+        });
+        self.journaled(Operation::FindById, || format!("id {id:?}"), result)
     }
 
     /// # Errors
     /// Locking may result in a error
     pub fn find_one(&self, args: FindArguments<V>) -> Result<Option<V>> {
-        self.find_many(args).map(|v| v.first().cloned())
+        self.latency.block(Operation::FindOne);
+        self.do_find_one(args)
+    }
+
+    pub(crate) fn do_find_one(&self, args: FindArguments<V>) -> Result<Option<V>> {
+        let arguments = find_arguments(&args);
+        let result = self.lock(Operation::FindOne).map(|storage| {
+            let found = self._find_many(&storage, args, false).into_iter().next();
+            let keys = found.iter().map(|(id, _)| id.clone()).collect();
+            (keys, found.map(|(_, value)| value))
+        });
+        self.journaled(Operation::FindOne, || arguments, result)
     }
 
     /// # Errors
     /// Locking may result in a error
     pub fn find_many(&self, args: FindArguments<V>) -> Result<Vec<V>> {
-        let storage = self.storage.lock().map_err(locking)?;
-        Ok(Self::_find_many(&storage, args))
+        self.latency.block(Operation::FindMany);
+        self.do_find_many(args)
+    }
+
+    pub(crate) fn do_find_many(&self, args: FindArguments<V>) -> Result<Vec<V>> {
+        let arguments = find_arguments(&args);
+        let result = self
+            .lock(Operation::FindMany)
+            .map(|storage| self._find_many(&storage, args, false).into_iter().unzip());
+        self.journaled(Operation::FindMany, || arguments, result)
     }
 
     fn _find_many(
-        storage: &MutexGuard<'_, HashMap<K, V>>,
-        FindArguments { matcher, order }: FindArguments<V>,
-    ) -> Vec<V> {
-        let mut matches: Vec<V> = storage.values().filter(matcher).cloned().collect();
-        if let Some(order) = order {
-            matches.sort_by(order);
+        &self,
+        storage: &Locked<'_, K, V>,
+        FindArguments { mut matcher, order }: FindArguments<V>,
+        with_deleted: bool,
+    ) -> Vec<(K, V)> {
+        let mut matches: Vec<(K, V)> = storage
+            .iter()
+            .filter(|(_, value)| (with_deleted || !self.is_deleted(value)) && matcher(value))
+            .map(|(id, value)| (id.clone(), value.clone()))
+            .collect();
+        if let Some(mut order) = order {
+            matches.sort_by(|(_, v1), (_, v2)| order(v1, v2));
         }
 
         matches
@@ -83,18 +290,60 @@ where
     /// # Errors
     ///  * Inserting a value with a in already insert results in a Conflict
     ///    error
+    ///  * A value without a valid id results in a InvalidId error
     ///  * Locking may result in a error
     pub fn insert(&self, value: V) -> Result<K> {
-        let id = self.identifier.new_id(&value);
-        let mut storage = self.storage.lock().map_err(locking)?;
+        self.latency.block(Operation::Insert);
+        self.do_insert(value)
+    }
+
+    pub(crate) fn do_insert(&self, value: V) -> Result<K> {
+        let mut batch = self.batch();
+        let result = self
+            .insert_value(value, None, &mut batch)
+            .map(|id| (vec![id.clone()], id));
+        let result = self.journaled(Operation::Insert, || "1 value".to_owned(), result);
+        self.notified(batch, result)
+    }
+
+    /// Inserts `value`, expiring it after `ttl` if set.
+    fn insert_value(
+        &self,
+        mut value: V,
+        ttl: Option<Duration>,
+        batch: &mut Batch<K, V>,
+    ) -> Result<K> {
+        let operation = Operation::Insert;
+        let id = self.new_id(operation, &value)?;
+        let mut generated = vec![id.clone()];
+        let mut storage = self.lock(operation).inspect_err(|_| {
+            self.identifier.release(&generated);
+        })?;
+        let id = self.retry_collision(operation, &storage, id, &value, &mut generated)?;
         if storage.get(&id).is_some() {
-            Err(Conflict {
-                key: format!("{id:?}"),
-            }
-            .into())
+            self.identifier.release(&generated);
+            Err(self.context(operation).conflict(&id))
         } else {
+            self.before_insert(operation, &id, &mut value)
+                .and_then(|()| self.after_insert(operation, &id, &value))
+                .inspect_err(|_| self.identifier.release(&generated))?;
+            self.log(operation, &storage, || {
+                vec![Change::Put {
+                    key: &id,
+                    value: &value,
+                }]
+            })
+            .inspect_err(|_| self.identifier.release(&generated))?;
+            batch.push(|| Event::Inserted {
+                key: id.clone(),
+                value: value.clone(),
+            });
             let key = id.clone();
             storage.insert(key, value);
+            self.committed(batch);
+            if let Some(ttl) = ttl {
+                self.expiry.set_ttl(id.clone(), self.clock.now(), ttl);
+            }
             Ok(id)
         }
     }
@@ -103,90 +352,197 @@ where
     ///  * Inserting a value with a in already insert results in a Conflict
     ///    error
     ///  * Inserting values with the same id results in a Cardinality error
+    ///  * A value without a valid id results in a InvalidId error
     ///  * Locking may result in a error
+    ///
+    /// Ids generated for values that were not stored are handed back to the
+    /// identifier through `FallibleIdentifier::release`.
     pub fn insert_many(&self, values: Vec<V>) -> Result<Vec<K>> {
-        self.check_cardinality(&values)?;
-        let storage = self.storage.lock().map_err(locking)?;
+        self.latency.block(Operation::InsertMany);
+        self.do_insert_many(values)
+    }
+
+    pub(crate) fn do_insert_many(&self, values: Vec<V>) -> Result<Vec<K>> {
+        let arguments = format!("{} values", values.len());
+        let mut batch = self.batch();
+        let result = self
+            .insert_values(values, &mut batch)
+            .map(|ids| (ids.clone(), ids));
+        let result = self.journaled(Operation::InsertMany, || arguments, result);
+        self.notified(batch, result)
+    }
+
+    fn insert_values(&self, values: Vec<V>, batch: &mut Batch<K, V>) -> Result<Vec<K>> {
+        let ids = self.check_cardinality(&values)?;
+        let storage = self.lock(Operation::InsertMany).inspect_err(|_| {
+            self.identifier.release(&ids);
+        })?;
 
-        self._insert_many(storage, values)
+        self._insert_many(storage, ids, values, batch)
     }
 
     fn _insert_many(
         &self,
-        mut storage: MutexGuard<'_, HashMap<K, V>>,
-        values: Vec<V>,
+        mut storage: Locked<'_, K, V>,
+        ids: Vec<K>,
+        mut values: Vec<V>,
+        batch: &mut Batch<K, V>,
     ) -> Result<Vec<K>> {
-        let mut stage_storage = HashMap::<K, V>::with_capacity(values.len());
-        let mut ids = Vec::with_capacity(values.len());
-        for value in values {
-            let id = self.identifier.new_id(&value);
-            if storage.get(&id).is_none() {
-                let key = id.clone();
-                ids.push(id);
-                stage_storage.insert(key, value);
-            } else {
-                return Err(Conflict {
-                    key: format!("{id:?}"),
-                }
-                .into());
-            }
+        let mut generated = ids.clone();
+        let mut retried = Vec::with_capacity(ids.len());
+        for (id, value) in ids.into_iter().zip(&values) {
+            retried.push(self.retry_collision(
+                Operation::InsertMany,
+                &storage,
+                id,
+                value,
+                &mut generated,
+            )?);
+        }
+        let ids = retried;
+        if let Some(id) = ids.iter().find(|id| storage.contains_key(id)) {
+            let err = self.context(Operation::InsertMany).conflict(id);
+            self.identifier.release(&generated);
+            return Err(err);
         }
-        storage.extend(stage_storage);
+        self.insert_hooks(&ids, &mut values)
+            .inspect_err(|_| self.identifier.release(&generated))?;
+        self.log(Operation::InsertMany, &storage, || {
+            ids.iter()
+                .zip(&values)
+                .map(|(key, value)| Change::Put { key, value })
+                .collect()
+        })
+        .inspect_err(|_| self.identifier.release(&generated))?;
+        for (key, value) in ids.iter().zip(&values) {
+            batch.push(|| Event::Inserted {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+        storage.extend(ids.iter().cloned().zip(values));
+        self.committed(batch);
 
         Ok(ids)
     }
 
+    fn insert_hooks(&self, ids: &[K], values: &mut [V]) -> Result<()> {
+        for (id, value) in ids.iter().zip(values.iter_mut()) {
+            self.before_insert(Operation::InsertMany, id, value)?;
+        }
+        for (id, value) in ids.iter().zip(values.iter()) {
+            self.after_insert(Operation::InsertMany, id, value)?;
+        }
+        Ok(())
+    }
+
     /// # Errors
     ///  * Updating a value not in storage results in a KeyNotFound error
+    ///  * A value without a valid id results in a InvalidId error
     ///  * Locking may result in a error
     pub fn update(&self, value: V) -> Result<()> {
-        let id = self.identifier.new_id(&value);
-        let id_err = id.clone();
-        let mut storage = self.storage.lock().map_err(locking)?;
-        storage
-            .get(&id)
-            .map(|_| ())
-            .and_then(|_| storage.insert(id, value).map(|_| {}))
-            .ok_or_else(|| {
-                KeyNotFound {
-                    key: format!("{id_err:?}"),
-                }
-                .into()
-            })
+        self.latency.block(Operation::Update);
+        self.do_update(value)
+    }
+
+    pub(crate) fn do_update(&self, value: V) -> Result<()> {
+        let mut batch = self.batch();
+        let result = self
+            .update_value(value, &mut batch)
+            .map(|id| (vec![id], ()));
+        let result = self.journaled(Operation::Update, || "1 value".to_owned(), result);
+        self.notified(batch, result)
+    }
+
+    fn update_value(&self, mut value: V, batch: &mut Batch<K, V>) -> Result<K> {
+        let operation = Operation::Update;
+        let id = self.new_id(operation, &value)?;
+        let mut storage = self.lock(operation)?;
+        if storage.get(&id).is_none_or(|old| self.is_deleted(old)) {
+            return Err(self.context(operation).key_not_found(&id));
+        }
+        self.before_update(operation, &id, &storage[&id], &mut value)?;
+        self.after_update(operation, &id, &storage[&id], &value)?;
+        self.log(operation, &storage, || {
+            vec![Change::Put {
+                key: &id,
+                value: &value,
+            }]
+        })?;
+        batch.push(|| Event::Updated {
+            key: id.clone(),
+            old: storage[&id].clone(),
+            new: value.clone(),
+        });
+        storage.insert(id.clone(), value);
+        self.committed(batch);
+
+        Ok(id)
     }
 
     /// # Errors
     ///  * Updating a values resulting in duplicated ids results in a Conflict
-    /// error
+    ///    error
+    ///  * A value without a valid id results in a InvalidId error
     ///  * Locking may result in a error
-    pub fn update_many(
+    pub fn update_many(&self, args: UpdateArguments<V>) -> Result<()> {
+        self.latency.block(Operation::UpdateMany);
+        self.do_update_many(args)
+    }
+
+    pub(crate) fn do_update_many(&self, args: UpdateArguments<V>) -> Result<()> {
+        let mut batch = self.batch();
+        let result = self.update_matches(args, &mut batch).map(|ids| (ids, ()));
+        let result = self.journaled(
+            Operation::UpdateMany,
+            || "matcher, updater".to_owned(),
+            result,
+        );
+        self.notified(batch, result)
+    }
+
+    fn update_matches(
         &self,
         UpdateArguments::<V> { matcher, updater }: UpdateArguments<V>,
-    ) -> Result<()> {
-        let mut storage = self.storage.lock().map_err(locking)?;
+        batch: &mut Batch<K, V>,
+    ) -> Result<Vec<K>> {
+        let mut storage = self.lock(Operation::UpdateMany)?;
+        let rows_before = storage.clone();
 
         let entries = self.remove_matches(&mut storage, matcher);
-        let entries_before = entries.clone();
-        let temp_storage = match self.update_to_temp_storage(updater, entries, &storage) {
-            Ok(s) => s,
-            Err(err) => {
-                storage.extend(entries_before);
-                return Err(err);
-            }
-        };
+        let removed: Vec<K> = entries.iter().map(|(id, _)| id.clone()).collect();
+        let temp_storage = self
+            .update_to_temp_storage(updater, entries, &storage, &rows_before)
+            .and_then(|temp_storage| {
+                self.log(Operation::UpdateMany, &rows_before, || {
+                    let removes = removed
+                        .iter()
+                        .filter(|key| !temp_storage.contains_key(key))
+                        .map(|key| Change::Remove { key });
+                    let puts = temp_storage
+                        .iter()
+                        .map(|(key, value)| Change::Put { key, value });
+                    removes.chain(puts).collect()
+                })?;
+                Ok(temp_storage)
+            })
+            .inspect_err(|_| *storage = rows_before.clone())?;
+        batch.push_updates(&rows_before, &removed, &temp_storage);
+        let ids = temp_storage.keys().cloned().collect();
         storage.extend(temp_storage);
+        self.committed(batch);
 
-        Ok(())
+        Ok(ids)
     }
 
     fn remove_matches(
         &self,
-        storage: &mut MutexGuard<'_, HashMap<K, V>>,
+        storage: &mut Locked<'_, K, V>,
         mut matcher: Box<Matcher<V>>,
     ) -> Vec<(K, V)> {
         let ids: Vec<_> = storage
             .iter()
-            .filter(|(_, v)| matcher(v))
+            .filter(|(_, v)| !self.is_deleted(v) && matcher(v))
             .map(|(id, _)| id)
             .cloned()
             .collect();
@@ -203,74 +559,302 @@ where
         &self,
         mut updater: Box<Updater<V>>,
         entries: Vec<(K, V)>,
-        storage: &MutexGuard<'_, HashMap<K, V>>,
+        storage: &Locked<'_, K, V>,
+        before: &Rows<K, V>,
     ) -> Result<HashMap<K, V>> {
+        let operation = Operation::UpdateMany;
         let mut temp_storage = HashMap::<K, V>::new();
+        let mut updated = Vec::with_capacity(entries.len());
 
-        for (id, mut value) in entries {
+        for (old_id, mut value) in entries {
             updater(&mut value);
+            self.before_update(operation, &old_id, &before[&old_id], &mut value)?;
 
             let id = if self.identifier.is_autogenerated() {
-                self.identifier.new_id(&value)
+                self.new_id(operation, &value)?
             } else {
-                id
+                old_id.clone()
             };
 
             if storage.get(&id).or_else(|| temp_storage.get(&id)).is_none() {
+                updated.push((old_id, id.clone()));
                 temp_storage.insert(id, value);
             } else {
-                return Err(Conflict {
-                    key: format!("{id:?}"),
-                }
-                .into());
+                return Err(self.context(operation).conflict(&id));
             }
         }
+        for (old_id, id) in &updated {
+            self.after_update(operation, id, &before[old_id], &temp_storage[id])?;
+        }
         Ok(temp_storage)
     }
 
     /// # Errors
     /// Locking may result in a error
     pub fn delete_by_id(&self, id: &K) -> Result<Option<V>> {
-        let mut storage = self.storage.lock().map_err(locking)?;
-        Ok(storage.remove(id))
+        self.latency.block(Operation::DeleteById);
+        self.do_delete_by_id(id)
+    }
+
+    pub(crate) fn do_delete_by_id(&self, id: &K) -> Result<Option<V>> {
+        let mut batch = self.batch();
+        let result = self.lock(Operation::DeleteById).and_then(|mut storage| {
+            let keys: Vec<K> = storage
+                .get(id)
+                .filter(|value| !self.is_deleted(value))
+                .map(|_| id.clone())
+                .into_iter()
+                .collect();
+            let removed =
+                self.delete_rows(Operation::DeleteById, &mut storage, &keys, &mut batch)?;
+            Ok((keys, removed.into_iter().next().flatten()))
+        });
+        let result = self.journaled(Operation::DeleteById, || format!("id {id:?}"), result);
+        self.notified(batch, result)
     }
 
     /// # Errors
     /// Locking may result in a error
-    pub fn delete_many<M: FnMut(&&V) -> bool>(&self, mut matcher: M) -> Result<Vec<Option<V>>> {
+    pub fn delete_many<M: FnMut(&&V) -> bool>(&self, matcher: M) -> Result<Vec<Option<V>>> {
+        self.latency.block(Operation::DeleteMany);
+        self.do_delete_many(matcher)
+    }
+
+    pub(crate) fn do_delete_many<M: FnMut(&&V) -> bool>(
+        &self,
+        mut matcher: M,
+    ) -> Result<Vec<Option<V>>> {
         // No Option
-        let mut storage = self.storage.lock().map_err(locking)?;
+        let mut batch = self.batch();
+        let result = self.lock(Operation::DeleteMany).and_then(|mut storage| {
+            let to_remove: Vec<_> = storage
+                .iter()
+                .filter(|(_, value)| !self.is_deleted(value) && matcher(value))
+                .map(|(id, _)| id)
+                .cloned()
+                .collect();
+
+            let removed =
+                self.delete_rows(Operation::DeleteMany, &mut storage, &to_remove, &mut batch)?;
+            Ok((to_remove, removed))
+        });
+        let result = self.journaled(Operation::DeleteMany, || "matcher".to_owned(), result);
+        self.notified(batch, result)
+    }
 
-        let to_remove: Vec<_> = storage
+    /// Removes the rows of `keys` from `storage` and returns them, or marks
+    /// them as deleted in soft delete mode.
+    fn delete_rows(
+        &self,
+        operation: Operation,
+        storage: &mut Locked<'_, K, V>,
+        keys: &[K],
+        batch: &mut Batch<K, V>,
+    ) -> Result<Vec<Option<V>>> {
+        self.delete_hooks(operation, storage, keys)?;
+        let Some(soft_delete) = &self.soft_delete else {
+            self.log(operation, storage, || {
+                keys.iter().map(|key| Change::Remove { key }).collect()
+            })?;
+            let removed: Vec<_> = keys.iter().map(|id| storage.remove(id)).collect();
+            for (key, value) in keys.iter().zip(&removed) {
+                if let Some(value) = value {
+                    batch.push(|| Event::Deleted {
+                        key: key.clone(),
+                        value: value.clone(),
+                    });
+                }
+            }
+            self.committed(batch);
+            return Ok(removed);
+        };
+
+        let now = self.clock.now();
+        let marked: Vec<(K, V)> = keys
             .iter()
-            .filter(|(_, value)| matcher(value))
-            .map(|(id, _)| id)
-            .cloned()
+            .filter_map(|key| {
+                Some((
+                    key.clone(),
+                    soft_delete.marked(storage.get(key)?, Some(now)),
+                ))
+            })
             .collect();
+        self.log(operation, storage, || {
+            marked
+                .iter()
+                .map(|(key, value)| Change::Put { key, value })
+                .collect()
+        })?;
+        for (key, value) in &marked {
+            batch.push(|| Event::Updated {
+                key: key.clone(),
+                old: storage[key].clone(),
+                new: value.clone(),
+            });
+        }
+        storage.extend(marked.iter().cloned());
+        self.committed(batch);
 
-        Ok(to_remove.iter().map(|id| storage.remove(id)).collect())
+        Ok(marked.into_iter().map(|(_, value)| Some(value)).collect())
     }
 
-    fn check_cardinality(&self, values: &[V]) -> Result<()> {
-        let mut ids = HashSet::<K>::with_capacity(values.len());
-        for value in values {
-            let id = self.identifier.new_id(value);
-            let key = format!("{id:?}");
+    /// Starts collecting the events of an operation, for its observers, the
+    /// history and the expiration times.
+    fn batch(&self) -> Batch<K, V> {
+        let mut batch = self.observers.batch();
+        if self.history.is_enabled() || self.expiry.is_enabled() {
+            batch.record();
+        }
+        batch
+    }
+
+    /// Records the events of `batch` in the history and the expiration
+    /// times, once the operation wrote them to storage.
+    fn committed(&self, batch: &Batch<K, V>) {
+        self.record_history(batch);
+        self.record_deadlines(batch);
+    }
 
-            if !ids.insert(id) {
-                return Err(Cardinality {
-                    key: format!("{key:?}"),
+    /// Replaces `id` with new ids while it is taken, up to collision_retries
+    /// times. The new ids are pushed to `generated`, the ids generated for the
+    /// operation, which are all released when no new id can be generated.
+    fn retry_collision(
+        &self,
+        operation: Operation,
+        storage: &Locked<'_, K, V>,
+        mut id: K,
+        value: &V,
+        generated: &mut Vec<K>,
+    ) -> Result<K> {
+        if self.identifier.is_autogenerated() {
+            return Ok(id);
+        }
+        for _ in 0..self.collision_retries {
+            if !storage.contains_key(&id) {
+                break;
+            }
+            id = self
+                .new_id(operation, value)
+                .inspect_err(|_| self.identifier.release(generated))?;
+            generated.push(id.clone());
+        }
+
+        Ok(id)
+    }
+
+    fn check_cardinality(&self, values: &[V]) -> Result<Vec<K>> {
+        let mut ids = Vec::with_capacity(values.len());
+        for value in values {
+            match self.new_id(Operation::InsertMany, value) {
+                Ok(id) => ids.push(id),
+                Err(err) => {
+                    self.identifier.release(&ids);
+                    return Err(err);
                 }
-                .into());
             }
         }
-        Ok(())
+        let mut unique = HashSet::<&K>::with_capacity(ids.len());
+        if let Some(id) = ids.iter().find(|id| !unique.insert(id)) {
+            let err = self.context(Operation::InsertMany).cardinality(id);
+            self.identifier.release(&ids);
+            return Err(err);
+        }
+
+        Ok(ids)
+    }
+
+    fn new_id(&self, operation: Operation, value: &V) -> Result<K> {
+        self.identifier
+            .try_new_id(value)
+            .map_err(|err| self.context(operation).invalid_id(err))
+    }
+
+    /// Locks the storage for `operation`, unless a fault is injected.
+    fn lock(&self, operation: Operation) -> Result<Locked<'_, K, V>> {
+        if let Some(fault) = self.faults.check(operation) {
+            return Err(self.context(operation).fault(fault));
+        }
+        self.lock_storage(operation)
     }
+
+    /// Locks the storage without injecting faults, once expired rows are
+    /// reclaimed.
+    fn lock_storage(&self, operation: Operation) -> Result<Locked<'_, K, V>> {
+        // Taken before the storage, see `foreign_key::Relations`.
+        let relations = self.relations.get().map(Relations::enter);
+        let storage = self
+            .storage
+            .lock()
+            .map_err(|err| self.context(operation).locking(err))?;
+        let mut storage = Locked {
+            storage,
+            _relations: relations,
+        };
+        self.reclaim_expired(operation, &mut storage)?;
+        Ok(storage)
+    }
+
+    /// Records the outcome of `operation` in the journal, if enabled, and
+    /// returns its value.
+    fn journaled<T, A: FnOnce() -> String>(
+        &self,
+        operation: Operation,
+        arguments: A,
+        result: Result<(Vec<K>, T)>,
+    ) -> Result<T> {
+        if self.journal.is_enabled() {
+            let (keys, outcome) = match &result {
+                Ok((keys, _)) => (keys.clone(), Ok(())),
+                Err(err) => (Vec::new(), Err(err.clone())),
+            };
+            self.journal.push(Record {
+                operation,
+                keys,
+                arguments: arguments(),
+                outcome,
+                at: self.clock.now(),
+            });
+        }
+
+        result.map(|(_, value)| value)
+    }
+
+    fn context(&self, operation: Operation) -> Context<'_> {
+        Context {
+            operation,
+            table: &self.name,
+        }
+    }
+}
+
+pub(crate) fn find_arguments<V>(args: &FindArguments<V>) -> String {
+    match args.order {
+        Some(_) => "matcher, order".to_owned(),
+        None => "matcher".to_owned(),
+    }
+}
+
+/// Name of the stored type without its path or generics.
+pub(crate) fn default_name<V>() -> String {
+    let name = std::any::type_name::<V>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name).to_owned()
+}
+
+/// Locks the state of an injector, journal or registry. Its entries are
+/// replaced, pushed and removed whole, so a poisoned lock is still usable.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
+    use fault::Fault;
+    use identifier::Rollback;
+    use latency::{Latency, Sleeper};
 
     #[derive(Clone)]
     pub struct Country {
@@ -282,20 +866,16 @@ mod tests {
 
     #[test]
     pub fn test_db_reads_from_hash_map() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![(
-                    378,
-                    Country {
-                        id: 378,
-                        name: "San Marino",
-                    },
-                )]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![(
+                378,
+                Country {
+                    id: 378,
+                    name: "San Marino",
+                },
+            )],
+        );
 
         let country = db
             .find_by_id(&378)
@@ -308,20 +888,16 @@ mod tests {
 
     #[test]
     pub fn test_db_fails_to_read_from_hash_map() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![(
-                    378,
-                    Country {
-                        id: 378,
-                        name: "San Marino",
-                    },
-                )]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![(
+                378,
+                Country {
+                    id: 378,
+                    name: "San Marino",
+                },
+            )],
+        );
 
         let error = db.find_by_id(&1).unwrap();
 
@@ -428,6 +1004,346 @@ mod tests {
         assert_eq!(countries.len(), 1);
     }
 
+    #[test]
+    pub fn test_db_insert_many_uses_one_id_per_value() {
+        let db = FakeDb::default();
+
+        let ids = db.insert_many(vec!["Chad", "Niger"]).unwrap();
+        let id = db.insert("Mali").unwrap();
+
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(id, 3);
+    }
+
+    #[test]
+    pub fn test_db_failed_insert_many_leaves_gap_in_sequence() {
+        let db = FakeDb::new(Sequence::new());
+        db.storage.lock().unwrap().insert(2, "Benin");
+
+        db.insert_many(vec!["Togo", "Ghana"])
+            .expect_err("id 2 is already in storage");
+        let id = db.insert("Togo").unwrap();
+
+        assert_eq!(id, 3);
+    }
+
+    #[test]
+    pub fn test_db_failed_insert_many_reclaims_sequence_ids() {
+        let db = FakeDb::new(Sequence::with_rollback(Rollback::Reclaim));
+        db.storage.lock().unwrap().insert(2, "Benin");
+
+        db.insert_many(vec!["Togo", "Ghana"])
+            .expect_err("id 2 is already in storage");
+        let id = db.insert("Togo").unwrap();
+
+        assert_eq!(id, 1);
+    }
+
+    #[derive(Clone)]
+    pub struct Draft {
+        pub id: Option<u32>,
+        pub name: &'static str,
+    }
+
+    impl_identifier!(DraftId<u32, Draft>, id?);
+
+    #[test]
+    pub fn test_db_fails_to_write_values_without_id() {
+        let db = FakeDb::new(DraftId);
+
+        let error = db.insert(Draft {
+            id: None,
+            name: "Atlantis",
+        });
+        let errors = db.insert_many(vec![
+            Draft {
+                id: Some(30),
+                name: "Greece",
+            },
+            Draft {
+                id: None,
+                name: "Atlantis",
+            },
+        ]);
+
+        assert!(error.is_err());
+        assert!(errors.is_err());
+        assert!(db.find_by_id(&30).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn test_db_fails_to_update_values_without_id() {
+        let db = FakeDb::new(DraftId);
+        db.insert(Draft {
+            id: Some(30),
+            name: "Greece",
+        })
+        .unwrap();
+
+        let error = db.update(Draft {
+            id: None,
+            name: "Atlantis",
+        });
+        let errors = db.update_many(args!(UpdateArguments<Draft> {
+            updater: |draft| draft.id = None,
+        }));
+
+        assert!(error.is_err());
+        assert!(errors.is_err());
+        let greece = db.find_by_id(&30).unwrap().expect("greece was lost");
+        assert_eq!(greece.name, "Greece");
+    }
+
+    #[test]
+    pub fn test_db_fails_to_insert_once_sequences_are_exhausted() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(u32::MAX, "Tuvalu")]);
+        let atomic = FakeDb::seeded(
+            identifier::AtomicSequence::new(),
+            vec![(u32::MAX, "Tuvalu")],
+        );
+
+        for error in [db.insert("Nauru"), atomic.insert("Nauru")] {
+            assert!(matches!(
+                error,
+                Err(FakeDbError::InvalidId { reason, .. })
+                    if reason == format!("sequence exhausted after {}", u32::MAX)
+            ));
+        }
+        assert_eq!(
+            db.find_many(FindArguments::default()).unwrap(),
+            vec!["Tuvalu"]
+        );
+    }
+
+    #[test]
+    pub fn test_db_seeded_sequence_skips_existing_keys() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Laos"), (4, "Cambodia")]);
+
+        let id = db.insert("Vietnam").unwrap();
+
+        assert_eq!(id, 5);
+    }
+
+    #[test]
+    pub fn test_db_retries_sequence_collisions() {
+        let db = FakeDb::new(Sequence::new()).with_collision_retries(2);
+        db.storage.lock().unwrap().insert(1, "Laos");
+        db.storage.lock().unwrap().insert(2, "Cambodia");
+
+        let id = db.insert("Vietnam").unwrap();
+        let ids = db.insert_many(vec!["Thailand"]).unwrap();
+
+        assert_eq!(id, 3);
+        assert_eq!(ids, vec![4]);
+    }
+
+    /// A reclaiming sequence that can not generate ids past `limit`, and does
+    /// not observe seeded keys.
+    struct Limited {
+        sequence: Sequence,
+        limit: u64,
+    }
+
+    impl<V> FallibleIdentifier<V> for Limited {
+        type Id = u32;
+
+        fn try_new_id(&self, value: &V) -> std::result::Result<u32, errors::IdError> {
+            if FallibleIdentifier::<V>::state(&self.sequence) >= Some(self.limit) {
+                return Err(errors::IdError {
+                    reason: "limit reached".to_owned(),
+                });
+            }
+            self.sequence.try_new_id(value)
+        }
+
+        fn is_autogenerated(&self) -> bool {
+            false
+        }
+
+        fn release(&self, ids: &[u32]) {
+            FallibleIdentifier::<V>::release(&self.sequence, ids);
+        }
+
+        fn state(&self) -> Option<u64> {
+            FallibleIdentifier::<V>::state(&self.sequence)
+        }
+    }
+
+    #[test]
+    pub fn test_db_reclaims_ids_when_a_collision_retry_fails() {
+        let limited = || Limited {
+            sequence: Sequence::with_rollback(Rollback::Reclaim),
+            limit: 2,
+        };
+        let seeds = vec![(1, "Laos"), (2, "Cambodia")];
+        let db = FakeDb::seeded(limited(), seeds.clone()).with_collision_retries(2);
+        let many = FakeDb::seeded(limited(), seeds).with_collision_retries(2);
+
+        let error = db.insert("Vietnam").unwrap_err();
+        many.insert_many(vec!["Thailand"]).unwrap_err();
+
+        assert!(matches!(error, FakeDbError::InvalidId { .. }));
+        assert_eq!(FallibleIdentifier::<&str>::state(&db.identifier), Some(0));
+        assert_eq!(FallibleIdentifier::<&str>::state(&many.identifier), Some(0));
+    }
+
+    #[test]
+    pub fn test_db_errors_name_operation_table_and_key() {
+        let db = FakeDb::new(CountryId).with_name("countries");
+
+        let error = db
+            .insert_many(vec![
+                Country {
+                    id: 852,
+                    name: "Hong Kong",
+                },
+                Country {
+                    id: 852,
+                    name: "Hong Kong",
+                },
+            ])
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            FakeDbError::Cardinality {
+                operation: Operation::InsertMany,
+                table: "countries".into(),
+                key: "852".into(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "insert_many on countries: key 852 is repeated"
+        );
+    }
+
+    #[test]
+    pub fn test_db_fails_with_injected_faults() {
+        let db = FakeDb::default().with_name("countries");
+        db.faults().fail_next(1, Fault::Timeout);
+        db.faults()
+            .fail_when(|operation| operation.is_delete(), Fault::ConnectionReset);
+
+        let timeout = db.insert("Andorra");
+        let id = db.insert("Monaco").unwrap();
+        let reset = db.delete_by_id(&id);
+
+        assert_eq!(
+            timeout,
+            Err(FakeDbError::Fault {
+                operation: Operation::Insert,
+                table: "countries".into(),
+                fault: Fault::Timeout,
+            })
+        );
+        assert_eq!(id, 2);
+        assert!(matches!(
+            reset,
+            Err(FakeDbError::Fault {
+                fault: Fault::ConnectionReset,
+                ..
+            })
+        ));
+        assert_eq!(db.find_by_id(&2).unwrap(), Some("Monaco"));
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingSleeper(std::sync::Arc<Mutex<Vec<Duration>>>);
+
+    impl Sleeper for RecordingSleeper {
+        fn sleep(&self, duration: Duration) {
+            self.0.lock().unwrap().push(duration);
+        }
+    }
+
+    #[test]
+    pub fn test_db_waits_for_latency_of_each_operation() {
+        let db = FakeDb::default();
+        let sleeper = RecordingSleeper::default();
+        db.latency().set_sleeper(sleeper.clone());
+        db.latency()
+            .set_for(Operation::Insert, Latency::Fixed(Duration::from_millis(20)));
+
+        let id = db.insert("Malta").unwrap();
+        db.find_by_id(&id).unwrap();
+        db.latency().set(Latency::Fixed(Duration::from_millis(5)));
+        db.find_by_id(&id).unwrap();
+
+        assert_eq!(
+            *sleeper.0.lock().unwrap(),
+            vec![Duration::from_millis(20), Duration::from_millis(5)]
+        );
+    }
+
+    #[test]
+    pub fn test_db_records_operations_in_journal() {
+        let db = FakeDb::default().with_journal();
+
+        let ids = db.insert_many(vec!["Fiji", "Samoa"]).unwrap();
+        db.find_by_id(&ids[0]).unwrap();
+        db.delete_by_id(&ids[1]).unwrap();
+        db.update("Tonga").expect_err("Tonga has no id");
+
+        let operations = db.operations();
+        assert_eq!(operations.len(), 4);
+        assert_eq!(operations[0].operation, Operation::InsertMany);
+        assert_eq!(operations[0].keys, vec![1, 2]);
+        assert_eq!(operations[0].arguments, "2 values");
+        assert_eq!(operations[1].keys, vec![1]);
+        assert_eq!(operations[2].operation, Operation::DeleteById);
+        assert!(operations[3].outcome.is_err());
+        db.assert_inserted(&1);
+        db.assert_deleted(&2);
+    }
+
+    #[test]
+    pub fn test_db_asserts_no_writes() {
+        let db = FakeDb::seeded(CountryId, vec![]).with_journal();
+
+        db.find_many(args!(FindArguments<Country> {})).unwrap();
+        db.delete_by_id(&1).unwrap();
+        db.update(Country {
+            id: 1,
+            name: "Nauru",
+        })
+        .expect_err("Nauru is not in storage");
+
+        db.assert_no_writes();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 3 to be inserted")]
+    pub fn test_db_assert_inserted_panics_when_key_was_not_inserted() {
+        let db = FakeDb::default().with_journal();
+
+        db.insert("Palau").unwrap();
+
+        db.assert_inserted(&3);
+    }
+
+    #[test]
+    pub fn test_db_fork_is_independent_from_parent() {
+        let fixture = FakeDb::seeded(Sequence::new(), vec![(1, "Gabon"), (2, "Congo")]);
+
+        let fork = fixture.fork().unwrap();
+        let id = fork.insert("Cameroon").unwrap();
+        fork.delete_by_id(&1).unwrap();
+        fixture
+            .update_many(UpdateArguments {
+                updater: Box::new(|name: &mut &str| *name = "Unknown"),
+                ..Default::default()
+            })
+            .unwrap();
+
+        assert_eq!(id, 3);
+        assert_eq!(fork.find_by_id(&1).unwrap(), None);
+        assert_eq!(fork.find_by_id(&2).unwrap(), Some("Congo"));
+        assert_eq!(fixture.find_by_id(&1).unwrap(), Some("Unknown"));
+        assert_eq!(fixture.find_by_id(&3).unwrap(), None);
+        assert_eq!(fixture.insert("Chad").unwrap(), 3);
+    }
+
     #[test]
     pub fn test_db_fails_to_write_when_a_entry_exists() {
         let db = FakeDb::new(CountryId);
@@ -442,22 +1358,54 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    pub fn test_db_errors_can_be_matched() {
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![(
+                7,
+                Country {
+                    id: 7,
+                    name: "Kazakhstan",
+                },
+            )],
+        );
+
+        let conflict = db.insert(Country {
+            id: 7,
+            name: "Russia",
+        });
+        let not_found = db.update(Country {
+            id: 993,
+            name: "Turkmenistan",
+        });
+
+        assert_eq!(
+            conflict,
+            Err(FakeDbError::Conflict {
+                operation: Operation::Insert,
+                table: "Country".into(),
+                key: "7".into(),
+            })
+        );
+        assert!(matches!(
+            not_found,
+            Err(FakeDbError::KeyNotFound { key, .. }) if key == "993"
+        ));
+    }
+
     #[test]
     pub fn test_db_updates_when_a_entry_exists() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![(
-                    55,
-                    Country {
-                        id: 55,
-                        name: "Uruguay",
-                    },
-                )]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![(
+                55,
+                Country {
+                    id: 55,
+                    name: "Uruguay",
+                },
+            )],
+        );
 
         let country = Country {
             id: 55,
@@ -472,36 +1420,32 @@ mod tests {
 
     #[test]
     pub fn test_db_update_many_with_custom_matcher() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![
-                    (
-                        51,
-                        Country {
-                            id: 51,
-                            name: "Peru",
-                        },
-                    ),
-                    (
-                        56,
-                        Country {
-                            id: 56,
-                            name: "Chile",
-                        },
-                    ),
-                    (
-                        506,
-                        Country {
-                            id: 506,
-                            name: "Costa Rica",
-                        },
-                    ),
-                ]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![
+                (
+                    51,
+                    Country {
+                        id: 51,
+                        name: "Peru",
+                    },
+                ),
+                (
+                    56,
+                    Country {
+                        id: 56,
+                        name: "Chile",
+                    },
+                ),
+                (
+                    506,
+                    Country {
+                        id: 506,
+                        name: "Costa Rica",
+                    },
+                ),
+            ],
+        );
 
         let args = args!(UpdateArguments<Country>{
             matcher: |&country| country.id < 506,
@@ -522,36 +1466,32 @@ mod tests {
 
     #[test]
     pub fn test_db_update_many_fails_when_id_is_duplicated() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![
-                    (
-                        51,
-                        Country {
-                            id: 51,
-                            name: "Peru",
-                        },
-                    ),
-                    (
-                        56,
-                        Country {
-                            id: 56,
-                            name: "Chile",
-                        },
-                    ),
-                    (
-                        506,
-                        Country {
-                            id: 506,
-                            name: "Costa Rica",
-                        },
-                    ),
-                ]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![
+                (
+                    51,
+                    Country {
+                        id: 51,
+                        name: "Peru",
+                    },
+                ),
+                (
+                    56,
+                    Country {
+                        id: 56,
+                        name: "Chile",
+                    },
+                ),
+                (
+                    506,
+                    Country {
+                        id: 506,
+                        name: "Costa Rica",
+                    },
+                ),
+            ],
+        );
 
         let args = args!(UpdateArguments<Country> {
             updater: |country| country.id = 51,
@@ -572,20 +1512,16 @@ mod tests {
 
     #[test]
     pub fn test_db_fails_to_update_when_a_entry_dont_exists() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![(
-                    1,
-                    Country {
-                        id: 1,
-                        name: "United States of America",
-                    },
-                )]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![(
+                1,
+                Country {
+                    id: 1,
+                    name: "United States of America",
+                },
+            )],
+        );
 
         let country = Country {
             id: 55,
@@ -598,36 +1534,32 @@ mod tests {
 
     #[test]
     fn test_delete_many_deletes_all_matches() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![
-                    (
-                        243,
-                        Country {
-                            id: 243,
-                            name: "Democratic Republic of the Congo",
-                        },
-                    ),
-                    (
-                        242,
-                        Country {
-                            id: 242,
-                            name: "Republic of the Congo",
-                        },
-                    ),
-                    (
-                        250,
-                        Country {
-                            id: 250,
-                            name: "Rwanda",
-                        },
-                    ),
-                ]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![
+                (
+                    243,
+                    Country {
+                        id: 243,
+                        name: "Democratic Republic of the Congo",
+                    },
+                ),
+                (
+                    242,
+                    Country {
+                        id: 242,
+                        name: "Republic of the Congo",
+                    },
+                ),
+                (
+                    250,
+                    Country {
+                        id: 250,
+                        name: "Rwanda",
+                    },
+                ),
+            ],
+        );
 
         db.delete_many(|country| country.id < 250).ok();
 
@@ -638,20 +1570,16 @@ mod tests {
 
     #[test]
     pub fn test_db_finds_by_custom_match() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![(
-                    506,
-                    Country {
-                        id: 506,
-                        name: "Costa Rica",
-                    },
-                )]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![(
+                506,
+                Country {
+                    id: 506,
+                    name: "Costa Rica",
+                },
+            )],
+        );
 
         let args = args!(FindArguments<Country> {
             matcher: |country: &&Country| country.name == "Costa Rica",
@@ -667,36 +1595,32 @@ mod tests {
 
     #[test]
     pub fn test_db_finds_many_by_custom_match() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![
-                    (
-                        11,
-                        Country {
-                            id: 11,
-                            name: "Armenia",
-                        },
-                    ),
-                    (
-                        10,
-                        Country {
-                            id: 10,
-                            name: "Argentina",
-                        },
-                    ),
-                    (
-                        9,
-                        Country {
-                            id: 9,
-                            name: "Afghanistan",
-                        },
-                    ),
-                ]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![
+                (
+                    11,
+                    Country {
+                        id: 11,
+                        name: "Armenia",
+                    },
+                ),
+                (
+                    10,
+                    Country {
+                        id: 10,
+                        name: "Argentina",
+                    },
+                ),
+                (
+                    9,
+                    Country {
+                        id: 9,
+                        name: "Afghanistan",
+                    },
+                ),
+            ],
+        );
 
         let args = args!(FindArguments<Country> {
             order: |c1: &Country, c2: &Country| c1.id.cmp(&c2.id),
@@ -713,29 +1637,25 @@ mod tests {
 
     #[test]
     pub fn test_db_deletes_correct_entry() {
-        let db = FakeDb {
-            identifier: CountryId,
-            storage: Mutex::new(
-                vec![
-                    (
-                        30,
-                        Country {
-                            id: 30,
-                            name: "Greece",
-                        },
-                    ),
-                    (
-                        90,
-                        Country {
-                            id: 90,
-                            name: "Turkey",
-                        },
-                    ),
-                ]
-                .into_iter()
-                .collect(),
-            ),
-        };
+        let db = FakeDb::seeded(
+            CountryId,
+            vec![
+                (
+                    30,
+                    Country {
+                        id: 30,
+                        name: "Greece",
+                    },
+                ),
+                (
+                    90,
+                    Country {
+                        id: 90,
+                        name: "Turkey",
+                    },
+                ),
+            ],
+        );
 
         let turkey = db
             .delete_by_id(&90)
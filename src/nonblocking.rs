@@ -0,0 +1,177 @@
+//! Async operations of a FakeDb, which wait for their latency with
+//! `tokio::time::sleep` so paused-time tests stay deterministic.
+use core::hash::Hash;
+
+use crate::{
+    args::{FindArguments, UpdateArguments},
+    identifier::FallibleIdentifier,
+    FakeDb, Operation, Result,
+};
+
+/// Async view of a FakeDb, returned by `FakeDb::nonblocking`.
+pub struct NonBlocking<'db, K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    db: &'db FakeDb<K, V, I>,
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    pub fn nonblocking(&self) -> NonBlocking<'_, K, V, I> {
+        NonBlocking { db: self }
+    }
+}
+
+impl<K, V, I> NonBlocking<'_, K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// See `FakeDb::find_by_id`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::find_by_id`
+    pub async fn find_by_id(&self, id: &K) -> Result<Option<V>> {
+        self.wait(Operation::FindById).await;
+        self.db.do_find_by_id(id)
+    }
+
+    /// See `FakeDb::find_one`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::find_one`
+    pub async fn find_one(&self, args: FindArguments<V>) -> Result<Option<V>> {
+        self.wait(Operation::FindOne).await;
+        self.db.do_find_one(args)
+    }
+
+    /// See `FakeDb::find_many`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::find_many`
+    pub async fn find_many(&self, args: FindArguments<V>) -> Result<Vec<V>> {
+        self.wait(Operation::FindMany).await;
+        self.db.do_find_many(args)
+    }
+
+    /// See `FakeDb::insert`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::insert`
+    pub async fn insert(&self, value: V) -> Result<K> {
+        self.wait(Operation::Insert).await;
+        self.db.do_insert(value)
+    }
+
+    /// See `FakeDb::insert_with_ttl`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::insert`
+    pub async fn insert_with_ttl(&self, value: V, ttl: std::time::Duration) -> Result<K> {
+        self.wait(Operation::Insert).await;
+        self.db.do_insert_with_ttl(value, ttl)
+    }
+
+    /// See `FakeDb::insert_many`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::insert_many`
+    pub async fn insert_many(&self, values: Vec<V>) -> Result<Vec<K>> {
+        self.wait(Operation::InsertMany).await;
+        self.db.do_insert_many(values)
+    }
+
+    /// See `FakeDb::update`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::update`
+    pub async fn update(&self, value: V) -> Result<()> {
+        self.wait(Operation::Update).await;
+        self.db.do_update(value)
+    }
+
+    /// See `FakeDb::update_many`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::update_many`
+    pub async fn update_many(&self, args: UpdateArguments<V>) -> Result<()> {
+        self.wait(Operation::UpdateMany).await;
+        self.db.do_update_many(args)
+    }
+
+    /// See `FakeDb::delete_by_id`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::delete_by_id`
+    pub async fn delete_by_id(&self, id: &K) -> Result<Option<V>> {
+        self.wait(Operation::DeleteById).await;
+        self.db.do_delete_by_id(id)
+    }
+
+    /// See `FakeDb::delete_many`.
+    ///
+    /// # Errors
+    /// Same as `FakeDb::delete_many`
+    pub async fn delete_many<M: FnMut(&&V) -> bool>(&self, matcher: M) -> Result<Vec<Option<V>>> {
+        self.wait(Operation::DeleteMany).await;
+        self.db.do_delete_many(matcher)
+    }
+
+    async fn wait(&self, operation: Operation) {
+        if let Some(duration) = self.db.latency().delay(operation) {
+            tokio::time::sleep(duration).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use crate::latency::Latency;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonblocking_operations_wait_for_latency() {
+        let db = FakeDb::default();
+        db.latency().set(Latency::Fixed(Duration::from_secs(1)));
+        db.latency()
+            .set_for(Operation::Insert, Latency::Fixed(Duration::from_secs(5)));
+
+        let start = Instant::now();
+        let id = db.nonblocking().insert("Iceland").await.unwrap();
+        let inserted = start.elapsed();
+        let iceland = db.nonblocking().find_by_id(&id).await.unwrap();
+
+        assert_eq!(inserted, Duration::from_secs(5));
+        assert_eq!(start.elapsed(), Duration::from_secs(6));
+        assert_eq!(iceland, Some("Iceland"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonblocking_latency_triggers_timeouts() {
+        let db = FakeDb::default();
+        db.latency()
+            .set_for(Operation::FindMany, Latency::Fixed(Duration::from_secs(30)));
+        let nonblocking = db.nonblocking();
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            nonblocking.find_many(FindArguments::<&str>::default()),
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+}
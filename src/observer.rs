@@ -0,0 +1,358 @@
+//! Change events of a FakeDb, delivered to subscribed callbacks and, with
+//! the `async` feature, to async streams.
+use core::hash::Hash;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crate::{identifier::FallibleIdentifier, lock, FakeDb, Result, Rows};
+
+/// A row changed by a write operation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<K, V> {
+    Inserted { key: K, value: V },
+    Updated { key: K, old: V, new: V },
+    Deleted { key: K, value: V },
+}
+
+impl<K, V> Event<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Self::Inserted { key, .. } | Self::Updated { key, .. } | Self::Deleted { key, .. } => {
+                key
+            }
+        }
+    }
+}
+
+/// Handle of a callback registered with `FakeDb::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subscription(u64);
+
+type Callback<K, V> = Arc<dyn Fn(&Event<K, V>) + Send + Sync>;
+
+/// Callbacks and streams the events of a FakeDb are delivered to.
+pub(crate) struct Observers<K, V> {
+    next_id: AtomicU64,
+    callbacks: Mutex<Vec<(Subscription, Callback<K, V>)>>,
+    #[cfg(feature = "async")]
+    senders: Mutex<Vec<tokio::sync::mpsc::UnboundedSender<Event<K, V>>>>,
+}
+
+impl<K, V> Default for Observers<K, V> {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(0),
+            callbacks: Mutex::new(Vec::new()),
+            #[cfg(feature = "async")]
+            senders: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Observers<K, V> {
+    /// Starts collecting the events of an operation, which are only built
+    /// when someone observes them.
+    pub fn batch(&self) -> Batch<K, V> {
+        #[cfg(feature = "async")]
+        let streaming = !lock(&self.senders).is_empty();
+        #[cfg(not(feature = "async"))]
+        let streaming = false;
+
+        Batch {
+            recording: streaming || !lock(&self.callbacks).is_empty(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Delivers the events of a committed operation. Callbacks run without
+    /// any lock held, so they may use the FakeDb.
+    pub fn notify(&self, batch: Batch<K, V>) {
+        if batch.events.is_empty() {
+            return;
+        }
+        let callbacks: Vec<Callback<K, V>> = lock(&self.callbacks)
+            .iter()
+            .map(|(_, callback)| callback.clone())
+            .collect();
+        for event in &batch.events {
+            for callback in &callbacks {
+                callback(event);
+            }
+        }
+        #[cfg(feature = "async")]
+        lock(&self.senders).retain(|sender| {
+            batch
+                .events
+                .iter()
+                .all(|event| sender.send(event.clone()).is_ok())
+        });
+    }
+}
+
+/// Events of an operation, delivered once it commits.
+pub(crate) struct Batch<K, V> {
+    recording: bool,
+    events: Vec<Event<K, V>>,
+}
+
+impl<K: Clone, V: Clone> Batch<K, V> {
+    /// Builds the events even without observers.
+    pub fn record(&mut self) {
+        self.recording = true;
+    }
+
+    pub fn events(&self) -> &[Event<K, V>] {
+        &self.events
+    }
+
+    pub fn push<E: FnOnce() -> Event<K, V>>(&mut self, event: E) {
+        if self.recording {
+            self.events.push(event());
+        }
+    }
+
+    /// Records the events of an update of the rows with `removed` keys in
+    /// `before` to the `updated` rows. A row whose key changed is deleted
+    /// and inserted.
+    pub fn push_updates(
+        &mut self,
+        before: &Rows<K, V>,
+        removed: &[K],
+        updated: &std::collections::HashMap<K, V>,
+    ) where
+        K: Eq + Hash,
+    {
+        if !self.recording {
+            return;
+        }
+        for key in removed {
+            let old = before[key].clone();
+            self.events.push(match updated.get(key) {
+                Some(new) => Event::Updated {
+                    key: key.clone(),
+                    old,
+                    new: new.clone(),
+                },
+                None => Event::Deleted {
+                    key: key.clone(),
+                    value: old,
+                },
+            });
+        }
+        for (key, value) in updated {
+            if !removed.contains(key) {
+                self.events.push(Event::Inserted {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Calls `callback` with every row changed by a write operation, once
+    /// the operation committed and released the storage lock. Events of
+    /// `insert_many`, `update_many` and `delete_many` are delivered together
+    /// after the whole batch is stored.
+    pub fn subscribe<F>(&self, callback: F) -> Subscription
+    where
+        F: Fn(&Event<K, V>) + Send + Sync + 'static,
+    {
+        let subscription = Subscription(self.observers.next_id.fetch_add(1, Ordering::Relaxed));
+        lock(&self.observers.callbacks).push((subscription, Arc::new(callback)));
+        subscription
+    }
+
+    /// Stops calling the callback of `subscription`. Returns `false` if it
+    /// was already unsubscribed.
+    pub fn unsubscribe(&self, subscription: Subscription) -> bool {
+        let mut callbacks = lock(&self.observers.callbacks);
+        let count = callbacks.len();
+        callbacks.retain(|(id, _)| *id != subscription);
+        callbacks.len() < count
+    }
+
+    /// Returns a stream of the events delivered to callbacks, see
+    /// `subscribe`. The stream ends when the FakeDb is dropped.
+    #[cfg(feature = "async")]
+    pub fn changes(&self) -> Changes<K, V> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        lock(&self.observers.senders).push(sender);
+        Changes { receiver }
+    }
+
+    /// Delivers the events of `batch` if `result` is the outcome of a
+    /// committed operation, and returns it.
+    pub(crate) fn notified<T>(&self, batch: Batch<K, V>, result: Result<T>) -> Result<T> {
+        if result.is_ok() {
+            self.observers.notify(batch);
+        }
+        result
+    }
+}
+
+/// Async stream of the events of a FakeDb, returned by `FakeDb::changes`.
+/// It implements `futures_core::Stream`, so stream combinators work on it.
+#[cfg(feature = "async")]
+pub struct Changes<K, V> {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<Event<K, V>>,
+}
+
+#[cfg(feature = "async")]
+impl<K, V> Changes<K, V> {
+    /// Waits for the next event, or returns `None` once the FakeDb is
+    /// dropped.
+    pub async fn next(&mut self) -> Option<Event<K, V>> {
+        self.receiver.recv().await
+    }
+
+    /// Returns the next event if one was already delivered.
+    pub fn try_next(&mut self) -> Option<Event<K, V>> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<K, V> futures_core::Stream for Changes<K, V> {
+    type Item = Event<K, V>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{args::UpdateArguments, identifier::Sequence};
+
+    use super::*;
+
+    type Recorded = Arc<Mutex<Vec<Event<u32, &'static str>>>>;
+
+    fn recorder(db: &FakeDb<u32, &'static str, Sequence>) -> (Subscription, Recorded) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let subscription = db.subscribe(move |event| recorded.lock().unwrap().push(event.clone()));
+        (subscription, events)
+    }
+
+    #[test]
+    fn test_subscribers_receive_committed_changes() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Bhutan")]);
+        let (_, events) = recorder(&db);
+
+        db.insert("Nepal").unwrap();
+        db.update_many(UpdateArguments {
+            matcher: Box::new(|name: &&&str| **name == "Bhutan"),
+            updater: Box::new(|name: &mut &str| *name = "Druk Yul"),
+        })
+        .unwrap();
+        db.delete_by_id(&2).unwrap();
+        db.delete_by_id(&2).unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::Inserted {
+                    key: 2,
+                    value: "Nepal"
+                },
+                Event::Updated {
+                    key: 1,
+                    old: "Bhutan",
+                    new: "Druk Yul"
+                },
+                Event::Deleted {
+                    key: 2,
+                    value: "Nepal"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_operations_emit_no_events() {
+        let db = FakeDb::new(Sequence::new());
+        db.storage.lock().unwrap().insert(2, "Bhutan");
+        let (_, events) = recorder(&db);
+
+        db.insert_many(vec!["Nepal", "Sikkim"])
+            .expect_err("id 2 is already in storage");
+
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unsubscribed_callbacks_are_not_called() {
+        let db = FakeDb::new(Sequence::new());
+        let (subscription, events) = recorder(&db);
+
+        assert!(db.unsubscribe(subscription));
+        db.insert("Nepal").unwrap();
+
+        assert!(events.lock().unwrap().is_empty());
+        assert!(!db.unsubscribe(subscription));
+    }
+
+    #[test]
+    fn test_callbacks_may_use_the_db() {
+        let db = Arc::new(FakeDb::new(Sequence::new()));
+        let inner = Arc::downgrade(&db);
+        db.subscribe(move |event| {
+            if let (Some(db), Event::Inserted { value: "Nepal", .. }) = (inner.upgrade(), event) {
+                db.insert("Sikkim").unwrap();
+            }
+        });
+
+        db.insert("Nepal").unwrap();
+
+        assert_eq!(db.find_by_id(&2).unwrap(), Some("Sikkim"));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_changes_stream_batches_after_commit() {
+        let db = FakeDb::new(Sequence::new());
+        let mut changes = db.changes();
+
+        db.insert_many(vec!["Bhutan", "Nepal"]).unwrap();
+        let first = changes.next().await.unwrap();
+        let second = changes.next().await.unwrap();
+
+        assert_eq!(first.key(), &1);
+        assert_eq!(second.key(), &2);
+        assert_eq!(changes.try_next(), None);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_changes_stream_ends_when_db_is_dropped() {
+        use futures_core::Stream;
+
+        async fn poll_next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+            std::future::poll_fn(|cx| std::pin::Pin::new(&mut *stream).poll_next(cx)).await
+        }
+
+        let db = FakeDb::new(Sequence::new());
+        let mut changes = db.changes();
+        db.insert("Bhutan").unwrap();
+        drop(db);
+
+        assert_eq!(
+            poll_next(&mut changes).await.map(|event| *event.key()),
+            Some(1)
+        );
+        assert_eq!(poll_next(&mut changes).await, None);
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// The FakeDb method being executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    FindById,
+    FindOne,
+    FindMany,
+    Insert,
+    InsertMany,
+    Update,
+    UpdateMany,
+    DeleteById,
+    DeleteMany,
+    /// Finding soft deleted rows too with `FakeDb::find_with_deleted`.
+    FindWithDeleted,
+    /// Undoing a soft delete with `FakeDb::restore_deleted`.
+    RestoreDeleted,
+    /// Removing soft deleted rows with `FakeDb::purge`.
+    Purge,
+    /// Copying the storage with `FakeDb::snapshot`.
+    Snapshot,
+    /// Resetting the storage with `FakeDb::restore`.
+    Restore,
+    /// Copying the FakeDb with `FakeDb::fork`.
+    Fork,
+    /// Serializing the storage, like `FakeDb::to_json` does.
+    Dump,
+    /// Creating a FakeDb from serialized rows, like `FakeDb::from_json` does.
+    Load,
+    /// Replacing the write-ahead log with a snapshot in `FakeDb::compact`.
+    Compact,
+}
+
+impl Operation {
+    pub fn is_find(&self) -> bool {
+        matches!(
+            self,
+            Self::FindById | Self::FindOne | Self::FindMany | Self::FindWithDeleted
+        )
+    }
+
+    pub fn is_insert(&self) -> bool {
+        matches!(self, Self::Insert | Self::InsertMany)
+    }
+
+    pub fn is_update(&self) -> bool {
+        matches!(self, Self::Update | Self::UpdateMany | Self::RestoreDeleted)
+    }
+
+    pub fn is_delete(&self) -> bool {
+        matches!(self, Self::DeleteById | Self::DeleteMany | Self::Purge)
+    }
+
+    /// Returns `true` for insert, update and delete operations.
+    pub fn is_write(&self) -> bool {
+        self.is_insert() || self.is_update() || self.is_delete()
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::FindById => "find_by_id",
+            Self::FindOne => "find_one",
+            Self::FindMany => "find_many",
+            Self::Insert => "insert",
+            Self::InsertMany => "insert_many",
+            Self::Update => "update",
+            Self::UpdateMany => "update_many",
+            Self::DeleteById => "delete_by_id",
+            Self::DeleteMany => "delete_many",
+            Self::FindWithDeleted => "find_with_deleted",
+            Self::RestoreDeleted => "restore_deleted",
+            Self::Purge => "purge",
+            Self::Snapshot => "snapshot",
+            Self::Restore => "restore",
+            Self::Fork => "fork",
+            Self::Dump => "dump",
+            Self::Load => "load",
+            Self::Compact => "compact",
+        };
+        f.write_str(name)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{identifier::FallibleIdentifier, FakeDb, Operation, Result, Rows};
+use core::hash::Hash;
+
+/// Immutable copy of the rows and identifier state of a FakeDb.
+///
+/// Taking and restoring a snapshot is O(1), as it shares the unchanged rows
+/// with the FakeDb.
+pub struct Snapshot<K, V> {
+    rows: Rows<K, V>,
+    identifier: Option<u64>,
+}
+
+impl<K, V> std::fmt::Debug for Snapshot<K, V>
+where
+    K: Eq + Hash + Clone + std::fmt::Debug,
+    V: Clone + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("rows", &self.rows)
+            .field("identifier", &self.identifier)
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Clone for Snapshot<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            rows: self.rows.clone(),
+            identifier: self.identifier,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Snapshot<K, V> {
+    pub fn get(&self, id: &K) -> Option<&V> {
+        self.rows.get(id)
+    }
+
+    pub fn rows(&self) -> &Rows<K, V> {
+        &self.rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// State of the identifier when the snapshot was taken, like the last id
+    /// of a `Sequence`.
+    pub fn identifier_state(&self) -> Option<u64> {
+        self.identifier
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Copies the rows and identifier state of this FakeDb.
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn snapshot(&self) -> Result<Snapshot<K, V>> {
+        let storage = self.lock_storage(Operation::Snapshot)?;
+        Ok(Snapshot {
+            rows: storage.clone(),
+            identifier: self.identifier.state(),
+        })
+    }
+
+    /// Resets the rows and identifier state of this FakeDb to `snapshot`.
+    ///
+    /// # Errors
+    ///  * An identifier state this identifier can not take, like one of
+    ///    another kind of identifier, results in a Format error
+    ///  * Locking may result in a error
+    pub fn restore(&self, snapshot: &Snapshot<K, V>) -> Result<()> {
+        let operation = Operation::Restore;
+        let mut storage = self.lock_storage(operation)?;
+        let previous = self.identifier.state();
+        if let Some(state) = snapshot.identifier {
+            self.identifier
+                .set_state(state)
+                .map_err(|err| self.context(operation).format(err.reason))?;
+        }
+        let state = snapshot.identifier.or(previous);
+        if let Err(err) = self.compact_log(operation, &snapshot.rows, state) {
+            if let Some(previous) = previous {
+                // A state returned by the identifier is always valid.
+                let _ = self.identifier.set_state(previous);
+            }
+            return Err(err);
+        }
+        storage.clone_from(&snapshot.rows);
+        self.expiry.reset(&storage);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::identifier::Sequence;
+
+    use super::*;
+
+    #[test]
+    fn test_restore_resets_rows_and_sequence() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Bhutan"), (2, "Nepal")]);
+        let snapshot = db.snapshot().unwrap();
+
+        db.insert("Tibet").unwrap();
+        db.delete_by_id(&1).unwrap();
+        db.restore(&snapshot).unwrap();
+        let id = db.insert("Sikkim").unwrap();
+
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.identifier_state(), Some(2));
+        assert_eq!(db.find_by_id(&1).unwrap(), Some("Bhutan"));
+        assert_eq!(id, 3);
+    }
+
+    #[test]
+    fn test_snapshot_is_not_changed_by_writes() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Bhutan")]);
+        let snapshot = db.snapshot().unwrap();
+
+        db.insert("Nepal").unwrap();
+
+        assert_eq!(snapshot.get(&1), Some(&"Bhutan"));
+        assert_eq!(snapshot.get(&2), None);
+    }
+}
@@ -0,0 +1,276 @@
+//! Soft deletes, which mark rows as deleted instead of removing them, like
+//! a `deleted_at` column.
+use core::hash::Hash;
+use std::{sync::Arc, time::SystemTime};
+
+use crate::{
+    args::FindArguments, identifier::FallibleIdentifier, observer::Event, wal::Change, FakeDb,
+    Operation, Result,
+};
+
+type Setter<V> = Arc<dyn Fn(&mut V, Option<SystemTime>) + Send + Sync>;
+type Getter<V> = Arc<dyn Fn(&V) -> Option<SystemTime> + Send + Sync>;
+
+/// Accessors of the deletion time of the values of a FakeDb, shared with
+/// its forks.
+pub(crate) struct SoftDelete<V> {
+    set: Setter<V>,
+    get: Getter<V>,
+}
+
+impl<V> Clone for SoftDelete<V> {
+    fn clone(&self) -> Self {
+        Self {
+            set: self.set.clone(),
+            get: self.get.clone(),
+        }
+    }
+}
+
+impl<V: Clone> SoftDelete<V> {
+    pub fn is_deleted(&self, value: &V) -> bool {
+        (self.get)(value).is_some()
+    }
+
+    /// Copy of `value` with `deleted_at` as its deletion time.
+    pub fn marked(&self, value: &V, deleted_at: Option<SystemTime>) -> V {
+        let mut value = value.clone();
+        (self.set)(&mut value, deleted_at);
+        value
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Soft deletes rows: `delete_by_id` and `delete_many` set their
+    /// deletion time with `set_deleted_at` instead of removing them. Rows for
+    /// which `deleted_at` returns a time are hidden from find, update and
+    /// delete operations, but still take their key.
+    ///
+    /// Soft deletes run the delete hooks and emit `Event::Updated`.
+    pub fn with_soft_delete<S, G>(mut self, set_deleted_at: S, deleted_at: G) -> Self
+    where
+        S: Fn(&mut V, Option<SystemTime>) + Send + Sync + 'static,
+        G: Fn(&V) -> Option<SystemTime> + Send + Sync + 'static,
+    {
+        self.soft_delete = Some(SoftDelete {
+            set: Arc::new(set_deleted_at),
+            get: Arc::new(deleted_at),
+        });
+        self
+    }
+
+    /// Like `find_many`, but also returns soft deleted rows.
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn find_with_deleted(&self, args: FindArguments<V>) -> Result<Vec<V>> {
+        let operation = Operation::FindWithDeleted;
+        self.latency.block(operation);
+        let arguments = crate::find_arguments(&args);
+        let result = self
+            .lock(operation)
+            .map(|storage| self._find_many(&storage, args, true).into_iter().unzip());
+        self.journaled(operation, || arguments, result)
+    }
+
+    /// Clears the deletion time of the soft deleted row of `id`, running the
+    /// update hooks, and returns it. Returns `None` if the row is not soft
+    /// deleted.
+    ///
+    /// # Errors
+    ///  * Rejected by a hook results in a Rejected error
+    ///  * Locking may result in a error
+    pub fn restore_deleted(&self, id: &K) -> Result<Option<V>> {
+        let operation = Operation::RestoreDeleted;
+        self.latency.block(operation);
+        let mut batch = self.batch();
+        let result = self.lock(operation).and_then(|mut storage| {
+            let Some(old) = storage.get(id).filter(|value| self.is_deleted(value)) else {
+                return Ok((Vec::new(), None));
+            };
+            let soft_delete = self.soft_delete.as_ref().expect("rows are soft deleted");
+            let mut value = soft_delete.marked(old, None);
+            self.before_update(operation, id, old, &mut value)?;
+            self.after_update(operation, id, old, &value)?;
+            self.log(operation, &storage, || {
+                vec![Change::Put {
+                    key: id,
+                    value: &value,
+                }]
+            })?;
+            batch.push(|| Event::Updated {
+                key: id.clone(),
+                old: old.clone(),
+                new: value.clone(),
+            });
+            storage.insert(id.clone(), value.clone());
+            self.committed(&batch);
+            Ok((vec![id.clone()], Some(value)))
+        });
+        let result = self.journaled(operation, || format!("id {id:?}"), result);
+        self.notified(batch, result)
+    }
+
+    /// Removes every soft deleted row and returns them. Delete hooks do not
+    /// run again.
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn purge(&self) -> Result<Vec<V>> {
+        let operation = Operation::Purge;
+        self.latency.block(operation);
+        let mut batch = self.batch();
+        let result = self.lock(operation).and_then(|mut storage| {
+            let keys: Vec<K> = storage
+                .iter()
+                .filter(|(_, value)| self.is_deleted(value))
+                .map(|(key, _)| key.clone())
+                .collect();
+            self.log(operation, &storage, || {
+                keys.iter().map(|key| Change::Remove { key }).collect()
+            })?;
+            let mut purged = Vec::with_capacity(keys.len());
+            for key in &keys {
+                let value = storage.remove(key).expect("unreachable");
+                batch.push(|| Event::Deleted {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+                purged.push(value);
+            }
+            self.committed(&batch);
+            Ok((keys, purged))
+        });
+        let result = self.journaled(operation, || "deleted rows".to_owned(), result);
+        self.notified(batch, result)
+    }
+
+    /// Returns `true` if `value` is soft deleted.
+    pub(crate) fn is_deleted(&self, value: &V) -> bool {
+        self.soft_delete
+            .as_ref()
+            .is_some_and(|soft_delete| soft_delete.is_deleted(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{clock::ManualClock, identifier::Sequence, test_support::all_by};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Order {
+        item: &'static str,
+        deleted_at: Option<SystemTime>,
+    }
+
+    fn order(item: &'static str) -> Order {
+        Order {
+            item,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn test_soft_deleted_rows_are_hidden() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, order("tea")), (2, order("scones"))],
+        )
+        .with_clock(ManualClock::new(
+            SystemTime::UNIX_EPOCH + Duration::from_secs(60),
+        ))
+        .with_soft_delete(
+            |order: &mut Order, deleted_at| order.deleted_at = deleted_at,
+            |order: &Order| order.deleted_at,
+        );
+
+        let deleted = db.delete_by_id(&1).unwrap().unwrap();
+
+        assert_eq!(
+            deleted.deleted_at,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(60))
+        );
+        assert_eq!(db.find_by_id(&1).unwrap(), None);
+        assert_eq!(
+            db.find_many(all_by(|order| order.item)).unwrap(),
+            vec![order("scones")]
+        );
+        assert_eq!(db.delete_by_id(&1).unwrap(), None);
+        assert_eq!(
+            db.find_with_deleted(all_by(|order| order.item)).unwrap(),
+            vec![order("scones"), deleted]
+        );
+    }
+
+    #[test]
+    fn test_restore_deleted_undoes_the_delete() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, order("tea")), (2, order("scones"))],
+        )
+        .with_soft_delete(
+            |order: &mut Order, deleted_at| order.deleted_at = deleted_at,
+            |order: &Order| order.deleted_at,
+        );
+        db.delete_many(|order| order.item == "tea").unwrap();
+
+        assert_eq!(db.restore_deleted(&1).unwrap(), Some(order("tea")));
+        assert_eq!(db.restore_deleted(&2).unwrap(), None);
+        assert_eq!(db.find_by_id(&1).unwrap(), Some(order("tea")));
+    }
+
+    #[test]
+    fn test_purge_removes_soft_deleted_rows() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, order("tea")), (2, order("scones"))],
+        )
+        .with_soft_delete(
+            |order: &mut Order, deleted_at| order.deleted_at = deleted_at,
+            |order: &Order| order.deleted_at,
+        )
+        .with_journal();
+        db.delete_by_id(&2).unwrap();
+
+        let purged = db.purge().unwrap();
+
+        assert_eq!(purged.len(), 1);
+        assert_eq!(
+            db.find_with_deleted(all_by(|order| order.item)).unwrap(),
+            vec![order("tea")]
+        );
+        db.assert_deleted(&2);
+    }
+
+    #[test]
+    fn test_forks_hide_soft_deleted_rows() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(1, order("tea")), (2, order("scones"))],
+        )
+        .with_soft_delete(
+            |order: &mut Order, deleted_at| order.deleted_at = deleted_at,
+            |order: &Order| order.deleted_at,
+        );
+        db.delete_by_id(&1).unwrap();
+
+        let fork = db.fork().unwrap();
+        fork.delete_by_id(&2).unwrap();
+
+        assert_eq!(fork.find_many(all_by(|order| order.item)).unwrap(), vec![]);
+        assert_eq!(fork.restore_deleted(&1).unwrap(), Some(order("tea")));
+        assert_eq!(
+            db.find_many(all_by(|order| order.item)).unwrap(),
+            vec![order("scones")]
+        );
+    }
+}
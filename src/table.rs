@@ -0,0 +1,369 @@
+//! Readable rendering of the rows of a FakeDb, for failing tests.
+use core::hash::Hash;
+use std::{cmp::Ordering, fmt};
+
+use crate::{diff::diff_rows, identifier::FallibleIdentifier, snapshot::Snapshot, FakeDb, Rows};
+
+/// Rows rendered as text, ordered by key.
+///
+/// Keys are compared by their Debug format, with digits compared as numbers
+/// so `2` comes before `10`. Display prints an aligned table, while `lines`
+/// returns a row per line without padding, which diffs well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    name: String,
+    columns: Vec<String>,
+    rows: Vec<(String, Vec<String>)>,
+}
+
+impl Table {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Names of the columns after the key.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Rows as `key | cell | cell`, in key order.
+    pub fn lines(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|(key, cells)| line(key, cells))
+            .collect()
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header: Vec<&str> = std::iter::once("key")
+            .chain(self.columns.iter().map(String::as_str))
+            .collect();
+        let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+        for (key, cells) in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(std::iter::once(key).chain(cells)) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let write_row = |f: &mut fmt::Formatter<'_>, cells: &mut dyn Iterator<Item = &str>| {
+            let padded: Vec<String> = cells
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            writeln!(f, "{}", padded.join(" | ").trim_end())
+        };
+
+        let rows = if self.rows.len() == 1 { "row" } else { "rows" };
+        writeln!(f, "{} ({} {rows})", self.name, self.rows.len())?;
+        write_row(f, &mut header.iter().copied())?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        writeln!(f, "{}", rule.join("-+-"))?;
+        for (key, cells) in &self.rows {
+            write_row(
+                f,
+                &mut std::iter::once(key).chain(cells).map(String::as_str),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn line(key: &str, cells: &[String]) -> String {
+    std::iter::once(key)
+        .chain(cells.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Compares `a` and `b` as text, except for runs of digits that are compared
+/// as numbers.
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        let ordering = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (x, rest_a) = split_digits(a);
+            let (y, rest_b) = split_digits(b);
+            (a, b) = (rest_a, rest_b);
+            let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            x.cmp(&y)
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+}
+
+fn split_digits(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    text.split_at(end)
+}
+
+fn render<K, V, C, P>(name: &str, rows: &Rows<K, V>, columns: C, mut project: P) -> Table
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: Clone,
+    C: IntoIterator,
+    C::Item: Into<String>,
+    P: FnMut(&V) -> Vec<String>,
+{
+    let mut rows: Vec<(String, Vec<String>)> = rows
+        .iter()
+        .map(|(key, value)| (format!("{key:?}"), project(value)))
+        .collect();
+    rows.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+
+    Table {
+        name: name.to_owned(),
+        columns: columns.into_iter().map(Into::into).collect(),
+        rows,
+    }
+}
+
+impl<K, V> Snapshot<K, V>
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: Clone,
+{
+    /// Renders the rows with a `value` column holding their Debug format.
+    pub fn table(&self, name: &str) -> Table
+    where
+        V: fmt::Debug,
+    {
+        self.table_with(name, ["value"], |value| vec![format!("{value:?}")])
+    }
+
+    /// Renders the rows with the cells `project` returns for each value, one
+    /// per column.
+    pub fn table_with<C, P>(&self, name: &str, columns: C, project: P) -> Table
+    where
+        C: IntoIterator,
+        C::Item: Into<String>,
+        P: FnMut(&V) -> Vec<String>,
+    {
+        render(name, self.rows(), columns, project)
+    }
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Renders the rows with a `value` column holding their Debug format.
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn table(&self) -> crate::Result<Table>
+    where
+        V: fmt::Debug,
+    {
+        Ok(self.snapshot()?.table(self.name()))
+    }
+
+    /// Renders the rows with the cells `project` returns for each value, one
+    /// per column:
+    ///
+    /// ```ignore
+    /// db.table_with(["name", "capital"], |c| vec![c.name.into(), c.capital.into()])?
+    /// ```
+    ///
+    /// # Errors
+    /// Locking may result in a error
+    pub fn table_with<C, P>(&self, columns: C, project: P) -> crate::Result<Table>
+    where
+        C: IntoIterator,
+        C::Item: Into<String>,
+        P: FnMut(&V) -> Vec<String>,
+    {
+        Ok(self.snapshot()?.table_with(self.name(), columns, project))
+    }
+
+    /// Asserts that the rows of this FakeDb are `expected`, see
+    /// `assert_db_eq!`.
+    ///
+    /// # Panics
+    /// Panics with the rows that differ, or if locking fails
+    #[track_caller]
+    pub fn assert_rows_eq<R>(&self, expected: R, message: Option<String>)
+    where
+        R: IntoIterator<Item = (K, V)>,
+        V: PartialEq + fmt::Debug,
+    {
+        let actual = self.snapshot().expect("rows could not be read");
+        let expected: Rows<K, V> = expected.into_iter().collect();
+
+        let diff = diff_rows(&expected, actual.rows());
+        if !diff.is_empty() {
+            panic!(
+                "{}rows of {} differ (- expected, + actual):\n{diff}",
+                message
+                    .map(|message| format!("{message}\n"))
+                    .unwrap_or_default(),
+                self.name(),
+            );
+        }
+    }
+}
+
+impl<K, V, I> fmt::Debug for FakeDb<K, V, I>
+where
+    K: Eq + Hash + fmt::Debug + Clone,
+    V: Clone + fmt::Debug,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Ok(rows) = self.storage.try_lock().map(|storage| storage.clone()) else {
+            return f.write_str("FakeDb { .. }");
+        };
+        let table = render(self.name(), &rows, ["value"], |value| {
+            vec![format!("{value:?}")]
+        });
+        write!(f, "FakeDb {table}")
+    }
+}
+
+/// Asserts that a FakeDb stores exactly the `(key, value)` rows of
+/// `expected`, panicking with the rows that differ otherwise.
+///
+/// ```ignore
+/// assert_db_eq!(db, [(1, "Bhutan"), (2, "Nepal")]);
+/// assert_db_eq!(db, [(1, "Bhutan")], "after deleting {}", 2);
+/// ```
+#[macro_export]
+macro_rules! assert_db_eq {
+    ($db: expr, $expected: expr $(,)?) => {
+        $db.assert_rows_eq($expected, ::std::option::Option::None)
+    };
+    ($db: expr, $expected: expr, $($message: tt)+) => {
+        $db.assert_rows_eq($expected, ::std::option::Option::Some(::std::format!($($message)+)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{clock::ManualClock, identifier::Sequence};
+
+    use super::*;
+
+    #[test]
+    fn test_natural_cmp_compares_digits_as_numbers() {
+        let mut keys = vec!["10", "2", "a10", "a9", "1", "b"];
+        keys.sort_by(|a, b| natural_cmp(a, b));
+
+        assert_eq!(keys, vec!["1", "2", "10", "a9", "a10", "b"]);
+    }
+
+    #[test]
+    fn test_table_is_ordered_by_key() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(10, "Nepal"), (2, "Bhutan"), (1, "Sikkim")],
+        )
+        .with_name("kingdoms");
+        let table = db.table().unwrap();
+
+        assert_eq!(
+            table.lines(),
+            vec![r#"1 | "Sikkim""#, r#"2 | "Bhutan""#, r#"10 | "Nepal""#]
+        );
+    }
+
+    #[test]
+    fn test_table_displays_projected_columns() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(10, "Nepal"), (2, "Bhutan"), (1, "Sikkim")],
+        )
+        .with_name("kingdoms");
+        let table = db
+            .table_with(["name", "letters"], |name| {
+                vec![name.to_string(), name.len().to_string()]
+            })
+            .unwrap();
+
+        assert_eq!(
+            table.to_string(),
+            "kingdoms (3 rows)\n\
+             key | name   | letters\n\
+             ----+--------+--------\n\
+             1   | Sikkim | 6\n\
+             2   | Bhutan | 6\n\
+             10  | Nepal  | 5\n"
+        );
+    }
+
+    #[test]
+    fn test_db_debug_prints_table() {
+        let db = FakeDb::seeded(Sequence::new(), vec![(1, "Sikkim")]).with_name("kingdoms");
+
+        assert_eq!(
+            format!("{db:?}"),
+            "FakeDb kingdoms (1 row)\nkey | value\n----+---------\n1   | \"Sikkim\"\n"
+        );
+    }
+
+    #[test]
+    fn test_db_debug_neither_waits_nor_reclaims() {
+        let clock = ManualClock::default();
+        let db = FakeDb::new(Sequence::new()).with_clock(clock.clone());
+        db.insert_with_ttl("Sikkim", Duration::from_secs(1))
+            .unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let debug = format!("{db:?}");
+        let storage = db.storage.lock().unwrap();
+
+        assert!(debug.ends_with("1   | \"Sikkim\"\n"));
+        assert!(storage.contains_key(&1));
+        assert_eq!(format!("{db:?}"), "FakeDb { .. }");
+    }
+
+    #[test]
+    fn test_assert_db_eq_passes_on_same_rows() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(10, "Nepal"), (2, "Bhutan"), (1, "Sikkim")],
+        )
+        .with_name("kingdoms");
+
+        assert_db_eq!(db, [(1, "Sikkim"), (2, "Bhutan"), (10, "Nepal")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "after annexation\n\
+        rows of kingdoms differ (- expected, + actual):\n\
+        + 1 | \"Sikkim\"\n\
+        - 2 | \"Bhutan \"\n\
+        + 2 | \"Bhutan\"\n\
+        + 10 | \"Nepal\"")]
+    fn test_assert_db_eq_shows_differing_rows() {
+        let db = FakeDb::seeded(
+            Sequence::new(),
+            vec![(10, "Nepal"), (2, "Bhutan"), (1, "Sikkim")],
+        )
+        .with_name("kingdoms");
+
+        assert_db_eq!(db, [(2, "Bhutan ")], "after {}", "annexation");
+    }
+}
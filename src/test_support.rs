@@ -0,0 +1,15 @@
+//! Helpers shared by the tests of several modules.
+use crate::args::FindArguments;
+
+/// Finds every row, in no particular order.
+pub(crate) fn all<V>() -> FindArguments<V> {
+    FindArguments::default()
+}
+
+/// Finds every row, ordered by the name `name` returns for it.
+pub(crate) fn all_by<V: 'static>(name: fn(&V) -> &str) -> FindArguments<V> {
+    FindArguments {
+        order: Some(Box::new(move |a, b| name(a).cmp(name(b)))),
+        ..all()
+    }
+}
@@ -0,0 +1,447 @@
+//! Write-ahead log that makes a FakeDb durable, with the `wal` feature.
+//!
+//! A FakeDb opened with `FakeDb::open` appends every change to `wal.jsonl`
+//! in its directory before applying it, one JSON line per operation, and
+//! replays the log when opened again. Every `compact_every` operations the
+//! rows are written to `snapshot.json` in the format of `FakeDb::to_json`
+//! and the log starts over.
+use core::hash::Hash;
+
+use crate::{identifier::FallibleIdentifier, FakeDb, Operation, Result, Rows};
+
+/// A row written or removed by an operation.
+// Only the log of the `wal` feature reads the fields.
+#[cfg_attr(not(feature = "wal"), allow(dead_code))]
+#[cfg_attr(feature = "wal", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "wal", serde(rename_all = "snake_case"))]
+pub(crate) enum Change<K, V> {
+    Put { key: K, value: V },
+    Remove { key: K },
+}
+
+/// Durable storage of the changes of a FakeDb.
+pub(crate) trait Log<K, V>: Send + Sync + std::fmt::Debug {
+    /// Persists `changes` before they are applied to `rows`, along with the
+    /// identifier state.
+    fn append(
+        &self,
+        rows: &Rows<K, V>,
+        identifier: Option<u64>,
+        changes: &[Change<&K, &V>],
+    ) -> std::io::Result<()>;
+
+    /// Persists `rows` whole, so the changes logged so far can be dropped.
+    fn compact(&self, rows: &Rows<K, V>, identifier: Option<u64>) -> std::io::Result<()>;
+}
+
+impl<K, V, I> FakeDb<K, V, I>
+where
+    K: Eq + Hash + std::fmt::Debug + Clone,
+    V: Clone,
+    I: FallibleIdentifier<V, Id = K>,
+{
+    /// Appends the changes `operation` is about to apply to `rows` to the
+    /// write-ahead log, if the FakeDb has one.
+    pub(crate) fn log<'a, C>(
+        &self,
+        operation: Operation,
+        rows: &Rows<K, V>,
+        changes: C,
+    ) -> Result<()>
+    where
+        C: FnOnce() -> Vec<Change<&'a K, &'a V>>,
+        K: 'a,
+        V: 'a,
+    {
+        let Some(log) = &self.log else {
+            return Ok(());
+        };
+        let changes = changes();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        log.append(rows, self.identifier.state(), &changes)
+            .map_err(|err| self.context(operation).io(err))
+    }
+
+    /// Replaces the write-ahead log, if the FakeDb has one, with `rows` and
+    /// the `identifier` state.
+    pub(crate) fn compact_log(
+        &self,
+        operation: Operation,
+        rows: &Rows<K, V>,
+        identifier: Option<u64>,
+    ) -> Result<()> {
+        match &self.log {
+            Some(log) => log
+                .compact(rows, identifier)
+                .map_err(|err| self.context(operation).io(err)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "wal")]
+pub use file::Wal;
+
+#[cfg(feature = "wal")]
+mod file {
+    use std::{
+        fs::{self, File, OpenOptions},
+        io::{self, BufRead, BufReader, Write},
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        default_name,
+        errors::Context,
+        json::{Dump, Row},
+    };
+
+    const SNAPSHOT: &str = "snapshot.json";
+    const LOG: &str = "wal.jsonl";
+
+    /// Where and how often a durable FakeDb persists its rows.
+    #[derive(Debug, Clone)]
+    pub struct Wal {
+        dir: PathBuf,
+        compact_every: usize,
+    }
+
+    impl Wal {
+        /// Logs to files in `dir`, compacting every 1000 operations.
+        pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+            Self {
+                dir: dir.into(),
+                compact_every: 1000,
+            }
+        }
+
+        /// # Panics
+        /// Panics if `operations` is 0
+        pub fn compact_every(mut self, operations: usize) -> Self {
+            assert!(operations > 0, "compaction needs at least one operation");
+            self.compact_every = operations;
+            self
+        }
+    }
+
+    /// One line of the log.
+    #[derive(Serialize, Deserialize)]
+    struct Entry<C> {
+        identifier: Option<u64>,
+        changes: C,
+    }
+
+    #[derive(Debug)]
+    struct WalFile {
+        dir: PathBuf,
+        file: File,
+        compact_every: usize,
+        /// Operations logged since the last compaction.
+        logged: AtomicUsize,
+    }
+
+    impl WalFile {
+        /// Appends a line through `write`. The log is cut back to its length
+        /// before if the line is not fully written and synced, so the next
+        /// line does not continue a partial one.
+        fn write_line(&self, write: impl FnOnce(&File) -> io::Result<()>) -> io::Result<()> {
+            let len = self.file.metadata()?.len();
+            write(&self.file)
+                .and_then(|()| self.file.sync_data())
+                .inspect_err(|_| {
+                    // A partial line left by a failed cut is dropped by open.
+                    let _ = self.file.set_len(len);
+                })
+        }
+    }
+
+    impl<K: Serialize + Send + Sync, V: Serialize + Send + Sync> Log<K, V> for WalFile {
+        fn append(
+            &self,
+            rows: &Rows<K, V>,
+            identifier: Option<u64>,
+            changes: &[Change<&K, &V>],
+        ) -> io::Result<()> {
+            if self.logged.load(Ordering::Relaxed) >= self.compact_every {
+                self.compact(rows, identifier)?;
+            }
+            let mut line = serde_json::to_vec(&Entry {
+                identifier,
+                changes,
+            })?;
+            line.push(b'\n');
+            self.write_line(|mut file| file.write_all(&line))?;
+            self.logged.fetch_add(1, Ordering::Relaxed);
+
+            Ok(())
+        }
+
+        fn compact(&self, rows: &Rows<K, V>, identifier: Option<u64>) -> io::Result<()> {
+            let dump = Dump {
+                identifier,
+                rows: rows.iter().map(|(key, value)| Row { key, value }).collect(),
+            };
+            // Renaming is atomic, so a crash leaves the old or the new snapshot.
+            let temp = self.dir.join(format!("{SNAPSHOT}.tmp"));
+            let mut file = File::create(&temp)?;
+            serde_json::to_writer(&mut file, &dump)?;
+            file.sync_all()?;
+            fs::rename(&temp, self.dir.join(SNAPSHOT))?;
+            // Replaying changes already in the snapshot is harmless, so a
+            // crash before truncating loses nothing.
+            self.file.set_len(0)?;
+            self.logged.store(0, Ordering::Relaxed);
+
+            Ok(())
+        }
+    }
+
+    impl<K, V, I> FakeDb<K, V, I>
+    where
+        K: Eq + Hash + std::fmt::Debug + Clone + Serialize + DeserializeOwned + Send + Sync,
+        V: Clone + Serialize + DeserializeOwned + Send + Sync,
+        I: FallibleIdentifier<V, Id = K>,
+    {
+        /// Opens a FakeDb that persists its rows in the directory of `wal`,
+        /// creating it if needed. The rows and identifier state left by the
+        /// last FakeDb opened there are restored.
+        ///
+        /// # Errors
+        ///  * A file that can not be read or written results in an Io error
+        ///  * Invalid snapshot or log lines result in a Format error. The last
+        ///    line of the log is dropped instead if it was not fully written.
+        pub fn open(identifier: I, wal: Wal) -> Result<Self> {
+            let table = default_name::<V>();
+            let context = Context {
+                operation: Operation::Load,
+                table: &table,
+            };
+
+            fs::create_dir_all(&wal.dir).map_err(|err| context.io(err))?;
+            let (mut rows, mut state) = read_snapshot(&wal.dir.join(SNAPSHOT), &context)?;
+            let path = wal.dir.join(LOG);
+            let file = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(&path)
+                .map_err(|err| context.io(err))?;
+
+            let mut logged = 0;
+            let mut valid_len = 0;
+            let mut reader = BufReader::new(&file);
+            let mut line = String::new();
+            while reader.read_line(&mut line).map_err(|err| context.io(err))? > 0 {
+                match serde_json::from_str::<Entry<Vec<Change<K, V>>>>(&line) {
+                    Ok(entry) => {
+                        for change in entry.changes {
+                            match change {
+                                Change::Put { key, value } => rows.insert(key, value),
+                                Change::Remove { key } => rows.remove(&key),
+                            };
+                        }
+                        state = entry.identifier.or(state);
+                        logged += 1;
+                    }
+                    // A crash while appending leaves a line without newline.
+                    Err(_) if !line.ends_with('\n') => break,
+                    Err(err) => return Err(context.format(format!("{LOG}: {err}"))),
+                }
+                valid_len += line.len() as u64;
+                line.clear();
+            }
+            file.set_len(valid_len).map_err(|err| context.io(err))?;
+
+            // Seeding observes the keys after the state is set, so a state
+            // behind the stored keys does not hand them out again.
+            if let Some(state) = state {
+                identifier
+                    .set_state(state)
+                    .map_err(|err| context.format(err.reason))?;
+            }
+            let mut db = Self::seeded(identifier, rows);
+            db.log = Some(Box::new(WalFile {
+                dir: wal.dir,
+                file,
+                compact_every: wal.compact_every,
+                logged: AtomicUsize::new(logged),
+            }));
+
+            Ok(db)
+        }
+
+        /// Writes the rows to the snapshot of the write-ahead log, which then
+        /// starts over. Does nothing if the FakeDb was not opened with `open`.
+        ///
+        /// # Errors
+        ///  * A file that can not be written results in an Io error
+        ///  * Locking may result in a error
+        pub fn compact(&self) -> Result<()> {
+            let storage = self.lock_storage(Operation::Compact)?;
+            self.compact_log(Operation::Compact, &storage, self.identifier.state())
+        }
+    }
+
+    fn read_snapshot<K, V>(path: &Path, context: &Context<'_>) -> Result<(Rows<K, V>, Option<u64>)>
+    where
+        K: Eq + Hash + Clone + DeserializeOwned,
+        V: Clone + DeserializeOwned,
+    {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((Rows::new(), None)),
+            Err(err) => return Err(context.io(err)),
+        };
+        let dump: Dump<K, V> = serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| context.format(format!("{SNAPSHOT}: {err}")))?;
+        let rows = dump
+            .rows
+            .into_iter()
+            .map(|Row { key, value }| (key, value))
+            .collect();
+
+        Ok((rows, dump.identifier))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use tempfile::TempDir;
+
+        use crate::identifier::Sequence;
+
+        use super::*;
+
+        fn open(dir: &TempDir) -> FakeDb<u32, String, Sequence> {
+            FakeDb::open(Sequence::new(), Wal::new(dir.path()).compact_every(3)).unwrap()
+        }
+
+        fn find(db: &FakeDb<u32, String, Sequence>, id: u32) -> Option<String> {
+            db.find_by_id(&id).unwrap()
+        }
+
+        #[test]
+        fn test_open_replays_logged_changes() {
+            let dir = TempDir::new().unwrap();
+            let db = open(&dir);
+            db.insert_many(vec!["Bhutan".into(), "Nepal".into()])
+                .unwrap();
+            db.update_many(crate::args::UpdateArguments {
+                matcher: Box::new(|name: &&String| *name == "Nepal"),
+                updater: Box::new(|name: &mut String| name.push_str(" (Kathmandu)")),
+            })
+            .unwrap();
+            db.delete_by_id(&1).unwrap();
+            drop(db);
+
+            let db = open(&dir);
+            let id = db.insert("Sikkim".into()).unwrap();
+
+            assert_eq!(find(&db, 1), None);
+            assert_eq!(find(&db, 2).as_deref(), Some("Nepal (Kathmandu)"));
+            assert_eq!(id, 3);
+        }
+
+        #[test]
+        fn test_log_is_compacted_into_snapshot() {
+            let dir = TempDir::new().unwrap();
+            let db = open(&dir);
+            for name in ["Laos", "Myanmar", "Vietnam", "Cambodia"] {
+                db.insert(name.into()).unwrap();
+            }
+            drop(db);
+
+            let log = fs::read_to_string(dir.path().join(LOG)).unwrap();
+            let db = open(&dir);
+
+            assert!(dir.path().join(SNAPSHOT).is_file());
+            assert_eq!(log.lines().count(), 1);
+            assert_eq!(find(&db, 1).as_deref(), Some("Laos"));
+            assert_eq!(find(&db, 4).as_deref(), Some("Cambodia"));
+        }
+
+        #[test]
+        fn test_open_drops_partially_written_line() {
+            let dir = TempDir::new().unwrap();
+            let db = open(&dir);
+            db.insert("Laos".into()).unwrap();
+            drop(db);
+            let mut file = OpenOptions::new()
+                .append(true)
+                .open(dir.path().join(LOG))
+                .unwrap();
+            file.write_all(br#"{"identifier":2,"chan"#).unwrap();
+
+            let db = open(&dir);
+            db.insert("Myanmar".into()).unwrap();
+            drop(db);
+            let db = open(&dir);
+
+            assert_eq!(find(&db, 1).as_deref(), Some("Laos"));
+            assert_eq!(find(&db, 2).as_deref(), Some("Myanmar"));
+        }
+
+        #[test]
+        fn test_failed_append_does_not_corrupt_the_next_line() {
+            let dir = TempDir::new().unwrap();
+            let db = open(&dir);
+            db.insert("Laos".into()).unwrap();
+            let wal = WalFile {
+                dir: dir.path().to_owned(),
+                file: OpenOptions::new()
+                    .append(true)
+                    .open(dir.path().join(LOG))
+                    .unwrap(),
+                compact_every: 3,
+                logged: AtomicUsize::new(0),
+            };
+
+            wal.write_line(|mut file| {
+                file.write_all(br#"{"identifier":2,"chan"#)?;
+                Err(io::Error::other("disk full"))
+            })
+            .expect_err("the write fails halfway");
+            db.insert("Myanmar".into()).unwrap();
+            drop(db);
+            let db = open(&dir);
+
+            assert_eq!(find(&db, 1).as_deref(), Some("Laos"));
+            assert_eq!(find(&db, 2).as_deref(), Some("Myanmar"));
+        }
+
+        #[test]
+        fn test_open_moves_identifier_state_past_stored_keys() {
+            let dir = TempDir::new().unwrap();
+            fs::write(
+                dir.path().join(SNAPSHOT),
+                r#"{ "identifier": 6, "rows": [{ "key": 7, "value": "Laos" }] }"#,
+            )
+            .unwrap();
+
+            let db = open(&dir);
+
+            assert_eq!(db.insert("Myanmar".into()).unwrap(), 8);
+        }
+
+        #[test]
+        fn test_restore_is_persisted() {
+            let dir = TempDir::new().unwrap();
+            let db = open(&dir);
+            db.insert("Laos".into()).unwrap();
+            let snapshot = db.snapshot().unwrap();
+            db.insert("Myanmar".into()).unwrap();
+            db.restore(&snapshot).unwrap();
+            drop(db);
+
+            let db = open(&dir);
+
+            assert_eq!(find(&db, 1).as_deref(), Some("Laos"));
+            assert_eq!(find(&db, 2), None);
+        }
+    }
+}